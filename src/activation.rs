@@ -0,0 +1,141 @@
+//! XDG activation tokens: without one the compositor may open the launched app
+//! unfocused, or behind other windows.
+//!
+//! fenrir runs in a terminal and has no surface of its own, so new tokens are
+//! requested without a surface or an input serial. Compositors are free to hand
+//! out a token that doesn't grant focus then, or to ignore it: the app opens
+//! like it would without one.
+
+use std::error::Error;
+use std::os::fd::AsRawFd;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::{Connection, Dispatch, QueueHandle};
+use wayland_protocols::xdg::activation::v1::client::xdg_activation_token_v1::{self, XdgActivationTokenV1};
+use wayland_protocols::xdg::activation::v1::client::xdg_activation_v1::XdgActivationV1;
+
+use crate::utils::log_to_file;
+
+/// The launch waits for the token: a compositor that doesn't answer mustn't hang it
+const TOKEN_TIMEOUT: Duration = Duration::from_millis(500);
+
+struct TokenState {
+    token: Option<String>,
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for TokenState {
+    fn event(_: &mut Self, _: &WlRegistry, _: <WlRegistry as wayland_client::Proxy>::Event, _: &GlobalListContents, _: &Connection, _: &QueueHandle<Self>) {}
+}
+
+impl Dispatch<XdgActivationV1, ()> for TokenState {
+    fn event(_: &mut Self, _: &XdgActivationV1, _: <XdgActivationV1 as wayland_client::Proxy>::Event, _: &(), _: &Connection, _: &QueueHandle<Self>) {}
+}
+
+impl Dispatch<XdgActivationTokenV1, ()> for TokenState {
+    fn event(state: &mut Self, _: &XdgActivationTokenV1, event: xdg_activation_token_v1::Event, _: &(), _: &Connection, _: &QueueHandle<Self>) {
+        if let xdg_activation_token_v1::Event::Done { token } = event {
+            state.token = Some(token);
+        }
+    }
+}
+
+/// Compositors expire tokens soon: a launch later than this after startup asks for a new one
+const INHERITED_MAX_AGE: Duration = Duration::from_secs(10);
+
+/// The token fenrir was started with, and when it was read
+static INHERITED: Mutex<Option<(String, Instant)>> = Mutex::new(None);
+
+/// Keeps the token fenrir was started with, for the first launch. Called at startup
+pub fn init() {
+    let token = std::env::var("XDG_ACTIVATION_TOKEN").ok()
+        .or_else(|| std::env::var("DESKTOP_STARTUP_ID").ok())
+        .filter(|t| !t.is_empty());
+    if let Ok(mut inherited) = INHERITED.lock() {
+        *inherited = token.map(|t| (t, Instant::now()));
+    }
+}
+
+/// The inherited token, if it's still fresh. Tokens are single use: only the first launch gets it
+fn take_inherited(slot: &Mutex<Option<(String, Instant)>>, max_age: Duration) -> Option<String> {
+    let (token, read_at) = slot.lock().ok()?.take()?;
+    (read_at.elapsed() <= max_age).then_some(token)
+}
+
+/// Asks the compositor for a new token, through the xdg_activation_v1 protocol
+fn request_token(app_id: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let conn = Connection::connect_to_env()?;
+    let (globals, mut queue) = registry_queue_init::<TokenState>(&conn)?;
+    let qh = queue.handle();
+    let activation: XdgActivationV1 = globals.bind(&qh, 1..=1, ())?;
+    let token = activation.get_activation_token(&qh, ());
+    if let Some(app_id) = app_id {
+        token.set_app_id(app_id.to_string());
+    }
+    token.commit();
+
+    let mut state = TokenState { token: None };
+    let deadline = Instant::now() + TOKEN_TIMEOUT;
+    let result = loop {
+        queue.flush()?;
+        queue.dispatch_pending(&mut state)?;
+        if let Some(token) = state.token.take() {
+            break Ok(token);
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break Err("the compositor didn't answer in time".into());
+        }
+        // None: there are events to dispatch already
+        let Some(guard) = queue.prepare_read() else {
+            continue;
+        };
+        let mut fd = libc::pollfd { fd: guard.connection_fd().as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut fd, 1, remaining.as_millis() as libc::c_int) } > 0 {
+            guard.read()?;
+        }
+    };
+    token.destroy();
+    activation.destroy();
+    let _ = conn.flush();
+    result
+}
+
+/// Token for the next launch: the one given to fenrir, or a new one where the compositor supports it
+pub fn startup_token(app_id: Option<&str>) -> Option<String> {
+    if let Some(token) = take_inherited(&INHERITED, INHERITED_MAX_AGE) {
+        return Some(token);
+    }
+    if std::env::var("WAYLAND_DISPLAY").is_err() {
+        return None;
+    }
+    match request_token(app_id) {
+        Ok(token) => Some(token),
+        Err(e) => {
+            log_to_file(format!("No activation token: {}", e));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inherited_token_is_taken_once() {
+        let slot = Mutex::new(Some(("abc".to_string(), Instant::now())));
+        assert_eq!(take_inherited(&slot, INHERITED_MAX_AGE).as_deref(), Some("abc"));
+        assert_eq!(take_inherited(&slot, INHERITED_MAX_AGE), None);
+        assert_eq!(take_inherited(&Mutex::new(None), INHERITED_MAX_AGE), None);
+    }
+
+    #[test]
+    fn stale_inherited_token_is_dropped() {
+        let slot = Mutex::new(Some(("abc".to_string(), Instant::now())));
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(take_inherited(&slot, Duration::from_millis(1)), None);
+        assert!(slot.lock().unwrap().is_none());
+    }
+}
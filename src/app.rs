@@ -1,14 +1,97 @@
 // use std::fs;
 use std::path::{PathBuf};
 use std::error::Error;
+use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+use crate::mime::local_path;
+use crate::utils::shell_quote;
+
+/// Fields missing from the daemon index (an older daemon) get their default
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AppEntry {
+    /// Desktop ID, the file name without ".desktop"
+    pub id: String,
     pub name: String,
     pub exec: String,
     pub icon_path: Option<PathBuf>,
     pub comment: String,
-    pub terminal: bool
+    pub terminal: bool,
+    pub wm_class: Option<String>,
+    pub desktop_file: PathBuf,
+    /// Path= key: the directory the app has to be started in
+    pub working_dir: Option<PathBuf>,
+    /// StartupNotify= key, None when missing
+    pub startup_notify: Option<bool>,
+    /// MimeType= key
+    pub mime_types: Vec<String>,
+    pub generic_name: Option<String>,
+    pub categories: Vec<String>,
+    pub keywords: Vec<String>,
+    /// Names of the desktop actions, like "New Private Window"
+    pub actions: Vec<String>,
+}
+
+impl AppEntry {
+    /// Exec with the field codes expanded. `args` is inserted as-is (so `~` and quoting
+    /// work like in a shell) in place of the first %f/%F/%u/%U, or appended when there's none
+    pub fn expand_exec(&self, args: &str) -> String {
+        let mut result = String::new();
+        let mut args_used = false;
+        let mut chars = self.exec.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                result.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('%') => result.push('%'),
+                Some('f' | 'F' | 'u' | 'U') if !args_used => {
+                    result.push_str(args);
+                    args_used = true;
+                },
+                Some('i') => {
+                    if let Some(icon) = &self.icon_path {
+                        result.push_str(&format!("--icon {}", shell_quote(&icon.to_string_lossy())));
+                    }
+                },
+                Some('c') => result.push_str(&shell_quote(&self.name)),
+                Some('k') => result.push_str(&shell_quote(&self.desktop_file.to_string_lossy())),
+                // the other file codes, deprecated (%d, %D, %n, %N, %v, %m) and unknown codes are dropped
+                _ => {},
+            }
+        }
+        if !args_used && !args.trim().is_empty() {
+            result = format!("{} {}", result.trim_end(), args);
+        }
+        result.trim().to_string()
+    }
+
+    /// Name of the desktop file, as mimeapps.list refers to it
+    pub fn file_name(&self) -> String {
+        format!("{}.desktop", self.id)
+    }
+
+    /// First %f/%F/%u/%U code of Exec
+    fn file_code(&self) -> Option<char> {
+        let mut chars = self.exec.chars();
+        while let Some(c) = chars.next() {
+            if c == '%'
+                && let Some(code @ ('f' | 'F' | 'u' | 'U')) = chars.next() {
+                return Some(code);
+            }
+        }
+        None
+    }
+
+    /// `target` (a path or URL) quoted for Exec: %f/%F want a local path, %u/%U take anything
+    pub fn target_arg(&self, target: &str) -> String {
+        let arg = match (self.file_code(), local_path(target)) {
+            (Some('f' | 'F'), Some(path)) => path.to_string_lossy().to_string(),
+            _ => target.to_string(),
+        };
+        shell_quote(&arg)
+    }
 }
 
 use freedesktop_desktop_entry::{default_paths, get_languages_from_env, Iter};
@@ -25,13 +108,35 @@ pub fn load_app_entries() -> Result<Vec<AppEntry>, Box<dyn Error>> {
     for entry in entries {
         // let path_src = PathSource::guess_from(&entry.path);
         // println!("{:?}: {}\n---\n{}", path_src, entry.path.display(), entry);
-        if (&entry).no_display() == false {
+        if !entry.no_display() {
             results.push(AppEntry {
-                exec: (&entry).exec().unwrap_or_default().to_string(),
-                name: (&entry).name(&vec!["en"]).as_ref().unwrap().to_string(),
-                icon_path: resolve_icon_path((&entry).icon().unwrap_or_default().to_string()),
-                comment: (&entry).comment(&vec!["en"]).unwrap_or_default().to_string(),
-                terminal: (&entry).terminal()
+                id: entry.id().to_string(),
+                exec: entry.exec().unwrap_or_default().to_string(),
+                name: entry.name(&["en"]).as_ref().unwrap().to_string(),
+                icon_path: resolve_icon_path(entry.icon().unwrap_or_default().to_string()),
+                comment: entry.comment(&["en"]).unwrap_or_default().to_string(),
+                terminal: entry.terminal(),
+                wm_class: entry.startup_wm_class().map(|c| c.to_string()),
+                desktop_file: entry.path.clone(),
+                working_dir: entry.path().filter(|p| !p.is_empty()).map(PathBuf::from),
+                startup_notify: entry.desktop_entry("StartupNotify").map(|v| v == "true"),
+                mime_types: entry.mime_type().unwrap_or_default().iter()
+                    .filter(|m| !m.is_empty())
+                    .map(|m| m.to_string())
+                    .collect(),
+                generic_name: entry.generic_name(&locales).map(|n| n.to_string()),
+                categories: entry.categories().unwrap_or_default().iter()
+                    .filter(|c| !c.is_empty())
+                    .map(|c| c.to_string())
+                    .collect(),
+                keywords: entry.keywords(&locales).unwrap_or_default().iter()
+                    .filter(|k| !k.is_empty())
+                    .map(|k| k.to_string())
+                    .collect(),
+                actions: entry.actions().unwrap_or_default().iter()
+                    .filter(|a| !a.is_empty())
+                    .map(|a| entry.action_name(a, &locales).map(|n| n.to_string()).unwrap_or_else(|| a.to_string()))
+                    .collect(),
             });
         }
     }
@@ -79,10 +184,55 @@ fn extract_field(contents: &str, field: &str) -> Option<String> {
 */
 
 fn resolve_icon_path(icon_name: String) -> Option<PathBuf> {
-    let candidates = vec![
+    let candidates = [
         format!("/usr/share/icons/hicolor/48x48/apps/{}.png", icon_name),
         format!("/usr/share/pixmaps/{}.png", icon_name)
     ];
 
     candidates.into_iter().map(PathBuf::from).find(|p| p.exists())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(exec: &str) -> AppEntry {
+        AppEntry {
+            name: "My App".into(),
+            exec: exec.into(),
+            icon_path: Some("/icons/my app.png".into()),
+            desktop_file: "/apps/my.desktop".into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn field_codes() {
+        assert_eq!(app("code %F").expand_exec("~/proj"), "code ~/proj");
+        assert_eq!(app("mpv --player-operation-mode=pseudo-gui -- %U").expand_exec(""), "mpv --player-operation-mode=pseudo-gui --");
+        // only the first file code takes the arguments
+        assert_eq!(app("viewer %f %u").expand_exec("a.png"), "viewer a.png");
+        assert_eq!(app("app %i %c %k").expand_exec(""), "app --icon '/icons/my app.png' 'My App' '/apps/my.desktop'");
+        // deprecated and unknown codes are dropped
+        assert_eq!(app("app %d %m %z --flag").expand_exec(""), "app    --flag");
+    }
+
+    #[test]
+    fn percent_escape() {
+        assert_eq!(app("date +%%H:%%M").expand_exec(""), "date +%H:%M");
+        assert_eq!(app("printf 100%% %f").expand_exec("x"), "printf 100% x");
+    }
+
+    #[test]
+    fn arguments_are_inserted_as_typed() {
+        assert_eq!(app("gimp %f").expand_exec("'my file.png' ~/b.png"), "gimp 'my file.png' ~/b.png");
+        assert_eq!(app("sh -c \"echo hi\" %u").expand_exec("\"a b\""), "sh -c \"echo hi\" \"a b\"");
+    }
+
+    #[test]
+    fn no_field_code() {
+        assert_eq!(app("mpv").expand_exec("--shuffle dir"), "mpv --shuffle dir");
+        assert_eq!(app("mpv --fs ").expand_exec("dir"), "mpv --fs dir");
+        assert_eq!(app("mpv").expand_exec("  "), "mpv");
+    }
+}
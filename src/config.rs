@@ -0,0 +1,493 @@
+//! User configuration, read from $XDG_CONFIG_HOME/fenrir/config.toml
+
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use crate::niri::WorkspaceRef;
+use crate::utils::{log_to_file, shell_quote, xdg_home};
+
+/// Read section by section: a wrong one, unknown keys included, is reported and left to its defaults
+#[derive(Debug)]
+pub struct Config {
+    /// Terminal emulator, commands are run with `<terminal> -e sh -c <command>`
+    pub terminal: String,
+    pub search: SearchConfig,
+    pub projects: ProjectsConfig,
+    pub launch: LaunchConfig,
+    /// Per-app settings, keyed by desktop ID (with or without ".desktop")
+    pub apps: HashMap<String, AppConfig>,
+    /// Sets of apps launched together, each one on its workspace
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    pub theme: ThemeConfig,
+    pub layout: LayoutConfig,
+    pub columns: ColumnsConfig,
+    pub mouse: MouseConfig,
+    pub keys: KeysConfig,
+    /// Problems found while loading, shown in the UI
+    pub errors: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            terminal: std::env::var("TERMINAL").unwrap_or_else(|_| "kitty".into()),
+            search: SearchConfig::default(),
+            projects: ProjectsConfig::default(),
+            launch: LaunchConfig::default(),
+            apps: HashMap::new(),
+            profiles: BTreeMap::new(),
+            theme: ThemeConfig::default(),
+            layout: LayoutConfig::default(),
+            columns: ColumnsConfig::default(),
+            mouse: MouseConfig::default(),
+            keys: KeysConfig::default(),
+            errors: vec![],
+        }
+    }
+}
+
+impl Config {
+    /// Shell command running `command` inside a new terminal window. The command is
+    /// quoted and run by its own shell, the outer one doesn't split its arguments again
+    pub fn in_terminal(&self, command: &str) -> String {
+        format!("{} -e sh -c {}", self.terminal, shell_quote(command))
+    }
+
+    pub fn app(&self, id: &str) -> Option<&AppConfig> {
+        self.apps.get(id).or_else(|| self.apps.get(&format!("{}.desktop", id)))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LaunchConfig {
+    /// setsid | systemd | niri | kitty
+    pub backend: String,
+    /// Backend for apps with Terminal=true, the global one if missing
+    pub terminal_backend: Option<String>,
+    /// How long a launched child is watched for early failures when its window can't be detected.
+    /// Only niri reports new windows: elsewhere this is a fixed wait, cut short only by the child exiting
+    pub grace_ms: u64,
+    /// Max wait for the window of the launched app to appear (niri only)
+    pub ready_timeout_ms: u64,
+    /// Variables of fenrir's terminal that shouldn't leak into the launched apps
+    pub unset_env: Vec<String>,
+    /// Pause between the apps of a batch launch
+    pub batch_delay_ms: u64,
+}
+
+impl Default for LaunchConfig {
+    fn default() -> Self {
+        Self {
+            backend: "setsid".into(),
+            terminal_backend: None,
+            grace_ms: 600,
+            ready_timeout_ms: 3000,
+            unset_env: ["KITTY_WINDOW_ID", "KITTY_PID", "KITTY_LISTEN_ON", "KITTY_PUBLIC_KEY", "WINDOWID"].iter().map(|v| v.to_string()).collect(),
+            batch_delay_ms: 300,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub backend: Option<String>,
+    /// Variables added to the environment of the app
+    pub env: HashMap<String, String>,
+    /// Variables removed from the environment of the app, on top of the global ones
+    pub unset_env: Vec<String>,
+    /// Commands prefixed to Exec, like ["gamemoderun"] or ["prime-run"]
+    pub wrapper: Vec<String>,
+    /// Appended to Exec, like "--ozone-platform=wayland"
+    pub args: Option<String>,
+    /// Working directory, overrides the Path= key of the desktop file
+    pub cwd: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfileConfig {
+    /// Workspace of the apps that don't set their own
+    pub workspace: Option<WorkspaceRef>,
+    pub apps: Vec<ProfileApp>,
+}
+
+/// An app of a profile: a desktop ID (or app name) or a plain command
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileApp {
+    pub app: Option<String>,
+    pub command: Option<String>,
+    pub workspace: Option<WorkspaceRef>,
+}
+
+/// Colours are names ("yellow", "light-red"), "#rrggbb" or 0-255 indexes
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StyleConfig {
+    pub fg: Option<String>,
+    pub bg: Option<String>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underlined: bool,
+}
+
+impl StyleConfig {
+    fn fg(color: &str) -> Self {
+        Self { fg: Some(color.into()), ..Default::default() }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    /// Selected row
+    pub highlight: StyleConfig,
+    /// Main column: app names, file names...
+    pub text: StyleConfig,
+    /// Icons, times, labels
+    pub label: StyleConfig,
+    /// Second column: exec, branch, URL...
+    pub exec: StyleConfig,
+    /// Last column: comment, path...
+    pub comment: StyleConfig,
+    /// Hints in the filter row
+    pub hint: StyleConfig,
+    /// Missing or inactive things: exited sessions, muted volume
+    pub dim: StyleConfig,
+    /// Marked apps, attached sessions
+    pub good: StyleConfig,
+    /// Launch failures, dirty repositories, config errors
+    pub error: StyleConfig,
+    pub border: StyleConfig,
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        Self {
+            highlight: StyleConfig { bg: Some("blue".into()), ..Default::default() },
+            text: StyleConfig::default(),
+            label: StyleConfig::fg("gray"),
+            exec: StyleConfig::fg("yellow"),
+            comment: StyleConfig::fg("#808080"),
+            hint: StyleConfig::fg("#808080"),
+            dim: StyleConfig::fg("#646464"),
+            good: StyleConfig::fg("light-green"),
+            error: StyleConfig::fg("light-red"),
+            border: StyleConfig::default(),
+        }
+    }
+}
+
+pub const LAYOUT_BLOCKS: [&str; 4] = ["status", "command", "filter", "list"];
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutConfig {
+    /// Blocks from top to bottom, among LAYOUT_BLOCKS
+    pub order: Vec<String>,
+    /// Ratatoskr resources shown on each row of the status block
+    pub status_rows: Vec<Vec<String>>,
+    /// Details pane on the right of the apps list, shown at start
+    pub details: bool,
+    pub details_width: u16,
+    /// Below this terminal width the pane collapses
+    pub details_min_width: u16,
+    /// Up on the first row goes to the last one, and back. Past queries are then recalled with Ctrl+R only
+    pub wraparound: bool,
+    /// Rows kept visible above and below the selected one
+    pub scrolloff: usize,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self {
+            order: LAYOUT_BLOCKS.iter().map(|b| b.to_string()).collect(),
+            status_rows: vec![
+                ["loadavg", "ram", "disk", "temperature", "volume", "weather", "display"].iter().map(|r| r.to_string()).collect(),
+                vec!["network".into(), "battery".into()],
+            ],
+            details: true,
+            details_width: 48,
+            details_min_width: 120,
+            wraparound: false,
+            scrolloff: 3,
+        }
+    }
+}
+
+/// Optional columns of the apps list
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColumnsConfig {
+    pub exec: bool,
+    pub comment: bool,
+}
+
+impl Default for ColumnsConfig {
+    fn default() -> Self {
+        Self { exec: true, comment: true }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MouseConfig {
+    /// Off leaves the mouse to the terminal, for its own text selection
+    pub capture: bool,
+    pub double_click_ms: u64,
+    /// Ratatoskr resource -> command run when its status segment is clicked
+    pub actions: HashMap<String, String>,
+}
+
+impl Default for MouseConfig {
+    fn default() -> Self {
+        Self { capture: true, double_click_ms: 400, actions: HashMap::new() }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeysConfig {
+    /// "default", "emacs" or "vim"
+    pub preset: String,
+    /// Chord (like "ctrl+n" or "alt+enter") -> action, on top of the preset. "none" unbinds
+    pub bindings: HashMap<String, String>,
+    /// Same for the normal mode of the vim preset
+    pub normal: HashMap<String, String>,
+}
+
+impl Default for KeysConfig {
+    fn default() -> Self {
+        Self { preset: "default".into(), bindings: HashMap::new(), normal: HashMap::new() }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchConfig {
+    /// Keyword of the engine used when nothing else matches
+    pub default_engine: String,
+    /// keyword -> URL template, `{query}` is replaced with the encoded query
+    pub engines: HashMap<String, String>,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            default_engine: "ddg".into(),
+            engines: HashMap::new(),
+        }
+    }
+}
+
+impl SearchConfig {
+    /// Built-in engines, overridden by the user ones with the same keyword
+    pub fn all_engines(&self) -> HashMap<String, String> {
+        let mut engines: HashMap<String, String> = [
+            ("g", "https://www.google.com/search?q={query}"),
+            ("ddg", "https://duckduckgo.com/?q={query}"),
+            ("w", "https://en.wikipedia.org/wiki/Special:Search?search={query}"),
+            ("crates", "https://crates.io/search?q={query}"),
+            ("docs", "https://docs.rs/releases/search?query={query}"),
+            ("gh", "https://github.com/search?q={query}"),
+            ("yt", "https://www.youtube.com/results?search_query={query}"),
+        ].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        engines.extend(self.engines.clone());
+        engines
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectsConfig {
+    /// Directories scanned for git repositories, `~` is expanded
+    pub roots: Vec<String>,
+    /// How many directory levels below each root are scanned
+    pub depth: usize,
+    /// Editor command, `{path}` is replaced with the repository path.
+    /// Defaults to $EDITOR inside the terminal
+    pub editor: Option<String>,
+}
+
+impl Default for ProjectsConfig {
+    fn default() -> Self {
+        Self {
+            roots: vec!["~/Repositories".into()],
+            depth: 2,
+            editor: None,
+        }
+    }
+}
+
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => PathBuf::from(format!("{}{}", home, rest)),
+        _ => PathBuf::from(path),
+    }
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_home("XDG_CONFIG_HOME", ".config").map(|d| d.join("fenrir"))
+}
+
+pub fn load_config() -> Config {
+    let Some(path) = config_dir().map(|d| d.join("config.toml")) else {
+        return Config::default();
+    };
+    let Ok(contents) = std::fs::read_to_string(&path) else {
+        return Config::default();
+    };
+    let mut config = parse_config(&contents);
+    for error in config.errors.iter_mut() {
+        log_to_file(format!("Invalid config {}: {}", path.display(), error));
+        *error = format!("{}: {}", path.display(), error);
+    }
+    config
+}
+
+/// The first line of a TOML error is enough for the UI
+fn short_error(e: impl std::fmt::Display) -> String {
+    e.to_string().lines().next().unwrap_or_default().to_string()
+}
+
+/// Takes a section out of the table, None if it's missing or wrong
+fn section<T: DeserializeOwned>(table: &mut toml::Table, key: &str, errors: &mut Vec<String>) -> Option<T> {
+    let value = table.remove(key)?;
+    match value.try_into() {
+        Ok(section) => Some(section),
+        Err(e) => {
+            errors.push(format!("{}: {}, using the defaults", key, short_error(e)));
+            None
+        }
+    }
+}
+
+fn parse_config(contents: &str) -> Config {
+    let mut config = Config::default();
+    let mut table: toml::Table = match toml::from_str(contents) {
+        Ok(table) => table,
+        Err(e) => {
+            config.errors.push(format!("{}, using the defaults", short_error(e)));
+            return config;
+        }
+    };
+    let errors = &mut config.errors;
+    if let Some(terminal) = section(&mut table, "terminal", errors) { config.terminal = terminal; }
+    if let Some(search) = section(&mut table, "search", errors) { config.search = search; }
+    if let Some(projects) = section(&mut table, "projects", errors) { config.projects = projects; }
+    if let Some(launch) = section(&mut table, "launch", errors) { config.launch = launch; }
+    if let Some(apps) = section(&mut table, "apps", errors) { config.apps = apps; }
+    if let Some(profiles) = section(&mut table, "profiles", errors) { config.profiles = profiles; }
+    if let Some(theme) = section(&mut table, "theme", errors) { config.theme = theme; }
+    if let Some(layout) = section(&mut table, "layout", errors) { config.layout = layout; }
+    if let Some(columns) = section(&mut table, "columns", errors) { config.columns = columns; }
+    if let Some(mouse) = section(&mut table, "mouse", errors) { config.mouse = mouse; }
+    if let Some(keys) = section(&mut table, "keys", errors) { config.keys = keys; }
+    for key in table.keys() {
+        errors.push(format!("unknown key '{}'", key));
+    }
+    config.layout.order = validate_order(&config.layout.order, errors);
+    config
+}
+
+/// Drops the unknown and repeated blocks, the default order if "list" isn't there
+fn validate_order(order: &[String], errors: &mut Vec<String>) -> Vec<String> {
+    let mut result: Vec<String> = vec![];
+    for block in order {
+        if !LAYOUT_BLOCKS.contains(&block.as_str()) {
+            errors.push(format!("layout.order: unknown block '{}', blocks are {}", block, LAYOUT_BLOCKS.join(", ")));
+        } else if result.contains(block) {
+            errors.push(format!("layout.order: '{}' is there twice", block));
+        } else {
+            result.push(block.clone());
+        }
+    }
+    if !result.iter().any(|b| b == "list") {
+        errors.push("layout.order: \"list\" is required, using the default order".to_string());
+        return LayoutConfig::default().order;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrong_sections_keep_the_others() {
+        let config = parse_config(r##"
+terminal = "foot"
+
+[theme]
+highlight = { bg = "red" }
+text = { forground = "white" }
+
+[layout]
+details = false
+detials_width = 30
+
+[columns]
+exec = false
+
+[search]
+default_engine = "g"
+"##);
+        assert_eq!(config.terminal, "foot");
+        assert_eq!(config.search.default_engine, "g");
+        assert!(!config.columns.exec);
+        // the wrong sections are left to their defaults
+        assert_eq!(config.theme.highlight.bg.as_deref(), Some("blue"));
+        assert!(config.layout.details);
+        assert_eq!(config.errors.len(), 2);
+        assert!(config.errors[0].starts_with("theme: unknown field `forground`"), "{}", config.errors[0]);
+        assert!(config.errors[1].starts_with("layout: unknown field `detials_width`"), "{}", config.errors[1]);
+    }
+
+    #[test]
+    fn unknown_sections_and_syntax_errors() {
+        let config = parse_config("[colums]\nexec = false\n");
+        assert_eq!(config.errors, ["unknown key 'colums'"]);
+        assert!(config.columns.exec);
+
+        let config = parse_config("[launch]\nbacknd = \"systemd\"\n\n[apps.firefox]\nenv = {}\n\n[mouse]\ncapture = false\n");
+        assert_eq!(config.launch.backend, "setsid");
+        assert!(!config.mouse.capture);
+        assert_eq!(config.errors.len(), 1);
+        assert!(config.errors[0].starts_with("launch: unknown field `backnd`"), "{}", config.errors[0]);
+
+        let config = parse_config("[profiles.work]\napps = [{ app = \"firefox\", worksapce = 2 }]\n");
+        assert!(config.profiles.is_empty());
+        assert!(config.errors[0].starts_with("profiles: unknown field `worksapce`"), "{}", config.errors[0]);
+
+        let config = parse_config("[theme\n");
+        assert_eq!(config.errors.len(), 1);
+        assert!(config.errors[0].ends_with("using the defaults"));
+    }
+
+    #[test]
+    fn terminal_command_is_quoted() {
+        let config = Config { terminal: "printf '%s\\n'".into(), ..Default::default() };
+        let command = config.in_terminal("htop --filter 'my app'");
+        // the terminal gets the command as a single argument, untouched
+        let output = std::process::Command::new("sh").arg("-c").arg(&command).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "-e\nsh\n-c\nhtop --filter 'my app'\n");
+    }
+
+    #[test]
+    fn layout_order() {
+        let config = parse_config("[layout]\norder = [\"filter\", \"list\", \"filter\", \"sidebar\"]\n");
+        assert_eq!(config.layout.order, ["filter", "list"]);
+        assert_eq!(config.errors, [
+            "layout.order: 'filter' is there twice".to_string(),
+            format!("layout.order: unknown block 'sidebar', blocks are {}", LAYOUT_BLOCKS.join(", ")),
+        ]);
+
+        let config = parse_config("[layout]\norder = [\"filter\"]\n");
+        assert_eq!(config.layout.order, LayoutConfig::default().order);
+        assert_eq!(config.errors.len(), 1);
+    }
+}
@@ -0,0 +1,424 @@
+//! `fenrir --daemon`: keeps the app index, the decoded icons, the launch history
+//! and the Ratatoskr connection warm, and serves them to the UI over a Unix socket.
+//!
+//! The protocol is one JSON object per line. Clients send a `Request`, attached
+//! clients receive a stream of `DaemonEvent`s. Each `Icon` request gets an `Icon`
+//! event back, in order, on a connection of its own that the UI keeps open.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use image::{DynamicImage, RgbaImage};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::app::{load_app_entries, AppEntry};
+use crate::config::Config;
+use crate::data::{PartialMsg, RatatoskrSocket};
+use crate::history::LaunchHistory;
+use crate::icons;
+use crate::launcher::{Launch, LaunchFailure, LaunchRequest};
+use crate::utils::{log_to_file, shell_quote};
+
+/// Desktop files don't change often: rescan them once in a while
+const RESCAN_EVERY: Duration = Duration::from_secs(60);
+
+/// Scaled icons kept, they're all dropped when there are more: clients ask for few sizes
+const MAX_SCALED: usize = 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    /// Get the index, then keep receiving status updates and commands
+    Attach,
+    /// Bring up the UI with the given mode and query
+    Show { mode: Option<String>, query: Option<String> },
+    Launched { app_id: String },
+    /// The icon scaled to fit `width`x`height` pixels, answered with an `Icon` event
+    Icon { path: PathBuf, width: u32, height: u32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DaemonEvent {
+    Index { apps: Vec<AppEntry>, history: LaunchHistory },
+    Status(PartialMsg),
+    Show { mode: Option<String>, query: Option<String> },
+    /// RGBA pixels in base64, None if the file can't be decoded
+    Icon { width: u32, height: u32, rgba: Option<String> },
+    /// Not sent by the daemon: the client lost the connection
+    Disconnected,
+}
+
+/// In $XDG_RUNTIME_DIR, which only the user can access. There's no daemon without it:
+/// anybody could connect to a socket in /tmp
+pub fn socket_path() -> Option<PathBuf> {
+    std::env::var("XDG_RUNTIME_DIR").ok()
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(dir).join("fenrir.sock"))
+}
+
+fn connect() -> Option<UnixStream> {
+    UnixStream::connect(socket_path()?).ok()
+}
+
+fn send_line<T: serde::Serialize>(stream: &mut UnixStream, value: &T) -> io::Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+/// Sends a single request to the running daemon, false if there isn't one
+pub fn send_request(request: &Request) -> bool {
+    match connect() {
+        Some(mut stream) => send_line(&mut stream, request).is_ok(),
+        None => false,
+    }
+}
+
+/// A connection for icon requests, kept open for all the icons of a UI
+pub struct IconClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl IconClient {
+    /// Connects to the running daemon, None if there isn't one
+    pub fn connect() -> Option<Self> {
+        Self::new(connect()?)
+    }
+
+    fn new(stream: UnixStream) -> Option<Self> {
+        stream.set_read_timeout(Some(Duration::from_secs(1))).ok()?;
+        Some(Self { reader: BufReader::new(stream.try_clone().ok()?), writer: stream })
+    }
+
+    /// The icon scaled to fit `width`x`height` pixels. Ok(None) if the daemon can't decode the file,
+    /// an error if the connection is gone: a late answer would be taken for the next icon, so it
+    /// can't be used anymore
+    pub fn fetch(&mut self, path: &Path, width: u32, height: u32) -> io::Result<Option<RgbaImage>> {
+        send_line(&mut self.writer, &Request::Icon { path: path.to_path_buf(), width, height })?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        match serde_json::from_str(&line)? {
+            DaemonEvent::Icon { width, height, rgba: Some(rgba) } => {
+                Ok(STANDARD.decode(rgba).ok().and_then(|rgba| RgbaImage::from_raw(width, height, rgba)))
+            },
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Icons decoded once, and their scaled copies asked by the clients
+#[derive(Default)]
+struct IconCache {
+    originals: HashMap<PathBuf, Option<DynamicImage>>,
+    scaled: HashMap<(PathBuf, u32, u32), Option<RgbaImage>>,
+}
+
+impl IconCache {
+    /// Decodes the icons of new apps and forgets the ones no app uses anymore.
+    /// The files are decoded without holding the lock, clients keep being served
+    fn warm(cache: &Mutex<IconCache>, apps: &[AppEntry]) {
+        let paths: HashSet<&PathBuf> = apps.iter().filter_map(|a| a.icon_path.as_ref()).collect();
+        let missing: Vec<PathBuf> = {
+            let Ok(mut cache) = cache.lock() else {
+                return;
+            };
+            cache.originals.retain(|path, _| paths.contains(path));
+            cache.scaled.retain(|(path, _, _), _| paths.contains(path));
+            paths.into_iter().filter(|path| !cache.originals.contains_key(*path)).cloned().collect()
+        };
+        for path in missing {
+            let img = decode_icon(&path);
+            if let Ok(mut cache) = cache.lock() {
+                cache.originals.insert(path, img);
+            }
+        }
+    }
+
+    fn scaled(&mut self, path: &Path, width: u32, height: u32) -> Option<RgbaImage> {
+        let key = (path.to_path_buf(), width, height);
+        if let Some(img) = self.scaled.get(&key) {
+            return img.clone();
+        }
+        // an icon that isn't in the index, a custom one for example
+        let original = self.originals.entry(path.to_path_buf()).or_insert_with(|| decode_icon(path));
+        let img = original.as_ref().map(|img| icons::scale(img, width, height));
+        if self.scaled.len() >= MAX_SCALED {
+            self.scaled.clear();
+        }
+        self.scaled.insert(key, img.clone());
+        img
+    }
+}
+
+fn decode_icon(path: &Path) -> Option<DynamicImage> {
+    image::open(path).map_err(|e| log_to_file(format!("Cannot load icon {}: {}", path.display(), e))).ok()
+}
+
+pub struct DaemonClient {
+    stream: UnixStream,
+    pub rx: Receiver<DaemonEvent>,
+}
+
+impl DaemonClient {
+    /// Attaches to the running daemon, None if there isn't one
+    pub fn connect() -> Option<Self> {
+        Self::attach(connect()?)
+    }
+
+    fn attach(mut stream: UnixStream) -> Option<Self> {
+        send_line(&mut stream, &Request::Attach).ok()?;
+        let reader = stream.try_clone().ok()?;
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if let Ok(event) = serde_json::from_str::<DaemonEvent>(&line)
+                    && tx.send(event).is_err() {
+                    return;
+                }
+            }
+            let _ = tx.send(DaemonEvent::Disconnected);
+        });
+        Some(Self { stream, rx })
+    }
+
+    pub fn launched(&mut self, app_id: &str) {
+        let _ = send_line(&mut self.stream, &Request::Launched { app_id: app_id.to_string() });
+    }
+}
+
+pub fn run_daemon(config: &Config) -> io::Result<()> {
+    let Some(path) = socket_path() else {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "XDG_RUNTIME_DIR is not set, there's no private directory for the socket"));
+    };
+    if UnixStream::connect(&path).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("a daemon is already listening on {}", path.display())));
+    }
+    // stale socket of a daemon that didn't clean up
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    // the runtime dir is private already, the socket too in case it isn't
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    println!("fenrir daemon listening on {}", path.display());
+
+    let icon_cache = Arc::new(Mutex::new(IconCache::default()));
+    let (req_tx, req_rx) = channel::<(Request, UnixStream)>();
+    let cache = icon_cache.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let req_tx = req_tx.clone();
+            let cache = cache.clone();
+            std::thread::spawn(move || serve_client(stream, req_tx, &cache));
+        }
+    });
+
+    let (index_tx, index_rx) = channel::<Vec<AppEntry>>();
+    let mut apps = load_app_entries().unwrap_or_default();
+    warm_icons(&icon_cache, &apps);
+    let mut last_scan = Instant::now();
+    let mut history = LaunchHistory::load();
+    let mut sock = RatatoskrSocket::new("/tmp/ratatoskr.sock");
+    // last message of each resource, replayed to new clients
+    let mut statuses: HashMap<String, PartialMsg> = HashMap::new();
+    let mut attached: Vec<UnixStream> = vec![];
+    // UI terminals opened by `--show`, watched between requests like the UI watches its launches
+    let mut shows: Vec<Launch> = vec![];
+    // their children once the wait is over: the terminal keeps running, and must be reaped when it exits
+    let mut show_children: Vec<Child> = vec![];
+
+    loop {
+        sock.poll_messages();
+        while let Ok(msg) = sock.rx.try_recv() {
+            attached.retain_mut(|c| send_line(c, &DaemonEvent::Status(msg.clone())).is_ok());
+            statuses.insert(msg.resource.clone(), msg);
+        }
+
+        if last_scan.elapsed() >= RESCAN_EVERY {
+            last_scan = Instant::now();
+            let index_tx = index_tx.clone();
+            std::thread::spawn(move || {
+                let _ = index_tx.send(load_app_entries().unwrap_or_default());
+            });
+        }
+        if let Ok(new_apps) = index_rx.try_recv() {
+            apps = new_apps;
+            warm_icons(&icon_cache, &apps);
+            let index = DaemonEvent::Index { apps: apps.clone(), history: history.clone() };
+            attached.retain_mut(|c| send_line(c, &index).is_ok());
+        }
+
+        while let Ok((request, mut stream)) = req_rx.try_recv() {
+            match request {
+                Request::Attach => {
+                    // a stuck client must not block everybody else
+                    let _ = stream.set_write_timeout(Some(Duration::from_millis(500)));
+                    // standalone UIs may have recorded launches meanwhile
+                    history = LaunchHistory::load();
+                    let index = DaemonEvent::Index { apps: apps.clone(), history: history.clone() };
+                    let replay_ok = send_line(&mut stream, &index).is_ok()
+                        && statuses.values().all(|s| send_line(&mut stream, &DaemonEvent::Status(s.clone())).is_ok());
+                    if replay_ok {
+                        attached.push(stream);
+                    }
+                },
+                Request::Show { mode, query } => {
+                    if attached.is_empty() {
+                        match show_in_terminal(config, mode.as_deref(), query.as_deref()) {
+                            Ok(launch) => shows.push(launch),
+                            Err(failure) => log_to_file(format!("Cannot open the UI: {}", failure.error)),
+                        }
+                    } else {
+                        let event = DaemonEvent::Show { mode, query };
+                        attached.retain_mut(|c| send_line(c, &event).is_ok());
+                    }
+                },
+                Request::Launched { app_id } => history.record(&app_id),
+                // answered by the client thread
+                Request::Icon { .. } => {},
+            }
+        }
+
+        let mut i = 0;
+        while i < shows.len() {
+            match shows[i].poll() {
+                None => i += 1,
+                Some(result) => {
+                    if let Err(failure) = result {
+                        log_to_file(format!("Cannot open the UI: {}", failure.error));
+                    }
+                    show_children.extend(shows.remove(i).into_child());
+                },
+            }
+        }
+        show_children.retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn warm_icons(cache: &Arc<Mutex<IconCache>>, apps: &[AppEntry]) {
+    let cache = cache.clone();
+    let apps = apps.to_vec();
+    std::thread::spawn(move || IconCache::warm(&cache, &apps));
+}
+
+/// Reads the requests of a client and hands them to the main loop, icons are answered right away
+fn serve_client(mut stream: UnixStream, req_tx: Sender<(Request, UnixStream)>, cache: &Mutex<IconCache>) {
+    let Ok(reader) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(reader).lines().map_while(Result::ok) {
+        match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Icon { path, width, height }) => {
+                let img = cache.lock().ok().and_then(|mut cache| cache.scaled(&path, width, height));
+                let event = DaemonEvent::Icon {
+                    width: img.as_ref().map_or(0, |img| img.width()),
+                    height: img.as_ref().map_or(0, |img| img.height()),
+                    rgba: img.map(|img| STANDARD.encode(img.as_raw())),
+                };
+                if send_line(&mut stream, &event).is_err() {
+                    return;
+                }
+            },
+            Ok(request) => {
+                let Ok(writer) = stream.try_clone() else {
+                    return;
+                };
+                if req_tx.send((request, writer)).is_err() {
+                    return;
+                }
+            },
+            Err(e) => log_to_file(format!("Invalid daemon request '{}': {}", line, e)),
+        }
+    }
+}
+
+/// No UI attached: open one in a new terminal window, it attaches back to the daemon
+fn show_in_terminal(config: &Config, mode: Option<&str>, query: Option<&str>) -> Result<Launch, Box<LaunchFailure>> {
+    let exe = std::env::current_exe().map(|e| e.to_string_lossy().to_string()).unwrap_or_else(|_| "fenrir".into());
+    let mut command = shell_quote(&exe);
+    if let Some(mode) = mode {
+        command.push_str(&format!(" --mode {}", shell_quote(mode)));
+    }
+    if let Some(query) = query {
+        command.push_str(&format!(" --query {}", shell_quote(query)));
+    }
+    Launch::start(config, &LaunchRequest::command(&config.in_terminal(&command)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_request(reader: &mut BufReader<UnixStream>) -> Request {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn requests_and_events_round_trip() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let mut client = DaemonClient::attach(client).unwrap();
+        let mut reader = BufReader::new(server.try_clone().unwrap());
+        assert!(matches!(read_request(&mut reader), Request::Attach));
+
+        client.launched("firefox.desktop");
+        assert!(matches!(read_request(&mut reader), Request::Launched { app_id } if app_id == "firefox.desktop"));
+
+        let mut server = server;
+        let mut history = LaunchHistory::default();
+        history.apps.insert("firefox.desktop".into(), Default::default());
+        send_line(&mut server, &DaemonEvent::Index { apps: vec![], history }).unwrap();
+        send_line(&mut server, &DaemonEvent::Show { mode: Some("apps".into()), query: Some("fire fox".into()) }).unwrap();
+        drop(server);
+        drop(reader);
+
+        let events: Vec<DaemonEvent> = client.rx.iter().collect();
+        assert!(matches!(&events[0], DaemonEvent::Index { apps, history } if apps.is_empty() && history.apps.contains_key("firefox.desktop")));
+        assert!(matches!(&events[1], DaemonEvent::Show { mode: Some(m), query: Some(q) } if m == "apps" && q == "fire fox"));
+        assert!(matches!(events[2], DaemonEvent::Disconnected));
+        assert_eq!(events.len(), 3);
+    }
+
+    #[test]
+    fn icons_from_the_cache() {
+        let dir = std::env::temp_dir().join(format!("fenrir-daemon-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("icon.png");
+        RgbaImage::from_pixel(32, 16, image::Rgba([255, 0, 0, 255])).save(&path).unwrap();
+
+        let cache = Arc::new(Mutex::new(IconCache::default()));
+        let (client, server) = UnixStream::pair().unwrap();
+        let server_cache = cache.clone();
+        std::thread::spawn(move || serve_client(server, channel().0, &server_cache));
+
+        // the aspect ratio is kept, one connection serves every icon
+        let mut client = IconClient::new(client).unwrap();
+        let img = client.fetch(&path, 8, 8).unwrap().unwrap();
+        assert_eq!((img.width(), img.height()), (8, 4));
+        assert_eq!(img.get_pixel(4, 2), &image::Rgba([255, 0, 0, 255]));
+        assert!(client.fetch(&dir.join("missing.png"), 8, 8).unwrap().is_none());
+        assert!(client.fetch(&path, 16, 16).unwrap().is_some());
+
+        let cache = cache.lock().unwrap();
+        assert!(cache.scaled.contains_key(&(path.clone(), 8, 8)));
+        assert!(cache.originals[&path].is_some());
+        drop(cache);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
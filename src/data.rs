@@ -1,9 +1,9 @@
 use std::sync::mpsc::{Sender,Receiver,channel};
 use std::os::unix::net::UnixStream;
 use std::io::Read;
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 
-#[derive(Default, Deserialize, Debug)]
+#[derive(Default, Deserialize, Serialize, Debug, Clone)]
 pub struct PartialMsg {
     pub resource: String,
     pub warning: f64,
@@ -0,0 +1,50 @@
+//! Launch groups: named sets of apps launched together
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::history::state_dir;
+use crate::utils::save_json;
+
+#[derive(Debug, Default)]
+pub struct LaunchGroups {
+    /// group name -> desktop IDs, in launch order
+    pub groups: BTreeMap<String, Vec<String>>,
+}
+
+impl LaunchGroups {
+    fn path() -> Option<PathBuf> {
+        state_dir().map(|d| d.join("groups.json"))
+    }
+
+    pub fn load() -> Self {
+        let groups = Self::path()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default();
+        Self { groups }
+    }
+
+    pub fn filtered(&self, filter: &str) -> Vec<(&String, &Vec<String>)> {
+        self.groups.iter()
+            .filter(|(name, _)| name.to_lowercase().contains(&filter.to_lowercase()))
+            .collect()
+    }
+
+    /// Saves the group, replacing the one with the same name
+    pub fn save(&mut self, name: &str, app_ids: &[String]) {
+        self.groups.insert(name.trim().to_string(), app_ids.to_vec());
+        self.write();
+    }
+
+    pub fn delete(&mut self, name: &str) {
+        self.groups.remove(name);
+        self.write();
+    }
+
+    fn write(&self) {
+        if let Some(path) = Self::path() {
+            save_json(&path, &self.groups, "groups");
+        }
+    }
+}
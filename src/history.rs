@@ -0,0 +1,55 @@
+//! Launch history: how many times and when each app was launched
+
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::utils::{save_json, xdg_home};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub count: u32,
+    /// Unix timestamp of the last launch
+    pub last: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LaunchHistory {
+    /// Keyed by desktop ID
+    pub apps: HashMap<String, HistoryEntry>,
+}
+
+pub fn state_dir() -> Option<PathBuf> {
+    xdg_home("XDG_STATE_HOME", ".local/state").map(|d| d.join("fenrir"))
+}
+
+impl LaunchHistory {
+    fn path() -> Option<PathBuf> {
+        state_dir().map(|d| d.join("history.json"))
+    }
+
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default()
+    }
+
+    /// Records the launch on top of the file: the daemon and standalone UIs share it, and
+    /// each keeps its own copy in memory that may be stale by now
+    pub fn record(&mut self, app_id: &str) {
+        if Self::path().is_some_and(|p| p.exists()) {
+            *self = Self::load();
+        }
+        let entry = self.apps.entry(app_id.to_string()).or_default();
+        entry.count += 1;
+        entry.last = chrono::Local::now().timestamp();
+        self.save();
+    }
+
+    fn save(&self) {
+        if let Some(path) = Self::path() {
+            save_json(&path, self, "history");
+        }
+    }
+}
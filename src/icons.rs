@@ -0,0 +1,755 @@
+//! Icons in the terminal, with the best graphics protocol it speaks:
+//! - kitty: every image is uploaded once per size, with a virtual placement, and shown by Unicode
+//!   placeholder cells. They live in ratatui's buffer like any other text, so scrolling and redraws
+//!   don't need anything special (https://sw.kovidgoyal.net/kitty/graphics-protocol/#unicode-placeholders)
+//! - sixel and iTerm2: the image is printed over the frame once it's flushed, in cells ratatui skips
+//! - half-blocks: two coloured pixels per cell, works everywhere with true colours
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use image::{DynamicImage, RgbaImage};
+use ratatui::Frame;
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::{Color, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Clear, Paragraph};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::daemon::IconClient;
+use crate::utils::log_to_file;
+
+const PLACEHOLDER: char = '\u{10EEEE}';
+
+/// Combining chars encoding the row and the column of a placeholder cell: the first ones
+/// of kitty's rowcolumn-diacritics.txt
+const DIACRITICS: [char; 32] = [
+    '\u{0305}', '\u{030D}', '\u{030E}', '\u{0310}', '\u{0312}', '\u{033D}', '\u{033E}', '\u{033F}',
+    '\u{0346}', '\u{034A}', '\u{034B}', '\u{034C}', '\u{0350}', '\u{0351}', '\u{0352}', '\u{0357}',
+    '\u{035B}', '\u{0363}', '\u{0364}', '\u{0365}', '\u{0366}', '\u{0367}', '\u{0368}', '\u{0369}',
+    '\u{036A}', '\u{036B}', '\u{036C}', '\u{036D}', '\u{036E}', '\u{036F}', '\u{0483}', '\u{0484}',
+];
+
+/// Base64 payload per escape sequence, as the kitty protocol requires
+const CHUNK: usize = 4096;
+
+/// How long the terminal has to answer the capability queries
+const DETECT_TIMEOUT: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Kitty,
+    Sixel,
+    Iterm2,
+    HalfBlocks,
+    None,
+}
+
+impl Protocol {
+    pub fn from_name(name: &str) -> Option<Protocol> {
+        match name {
+            "kitty" => Some(Protocol::Kitty),
+            "sixel" => Some(Protocol::Sixel),
+            "iterm2" | "iterm" => Some(Protocol::Iterm2),
+            "halfblocks" | "blocks" => Some(Protocol::HalfBlocks),
+            "none" => Some(Protocol::None),
+            _ => None,
+        }
+    }
+
+    /// Asks the terminal what it supports: a kitty graphics query, the terminal name (XTGETTCAP TN)
+    /// and the primary device attributes (DA1), where 4 means sixel. Every terminal answers DA1, so
+    /// its reply ends the wait. Needs raw mode; keys typed in the meantime are lost
+    pub fn detect() -> Protocol {
+        if std::env::var("KITTY_WINDOW_ID").is_ok() {
+            return Protocol::Kitty;
+        }
+        let program = std::env::var("TERM_PROGRAM").or_else(|_| std::env::var("LC_TERMINAL")).unwrap_or_default();
+        if program == "iTerm.app" || program == "iTerm2" || program == "WezTerm" {
+            return Protocol::Iterm2;
+        }
+        if unsafe { libc::isatty(0) } != 1 {
+            return Protocol::HalfBlocks;
+        }
+        let mut stdout = std::io::stdout();
+        let queries = "\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\\x1bP+q544e\x1b\\\x1b[c";
+        if stdout.write_all(queries.as_bytes()).and_then(|_| stdout.flush()).is_err() {
+            return Protocol::HalfBlocks;
+        }
+        let replies = read_replies();
+        let protocol = protocol_from_replies(&replies);
+        log_to_file(format!("Icons: {:?} from {:?}", protocol, replies));
+        protocol
+    }
+}
+
+/// Reads stdin until the DA1 reply or the timeout
+fn read_replies() -> String {
+    let deadline = Instant::now() + DETECT_TIMEOUT;
+    let mut replies = Vec::new();
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let mut fds = libc::pollfd { fd: 0, events: libc::POLLIN, revents: 0 };
+        if left.is_zero() || unsafe { libc::poll(&mut fds, 1, left.as_millis() as i32) } <= 0 {
+            break;
+        }
+        let mut buf = [0u8; 256];
+        let n = unsafe { libc::read(0, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n <= 0 {
+            break;
+        }
+        replies.extend_from_slice(&buf[..n as usize]);
+        if da1_params(&String::from_utf8_lossy(&replies)).is_some() {
+            break;
+        }
+    }
+    String::from_utf8_lossy(&replies).to_string()
+}
+
+/// Parameters of the DA1 reply, ESC [ ? 62;4;22 c
+fn da1_params(replies: &str) -> Option<Vec<&str>> {
+    let start = replies.find("\x1b[?")? + 3;
+    let rest = &replies[start..];
+    let end = rest.find(|c: char| !c.is_ascii_digit() && c != ';')?;
+    (rest[end..].starts_with('c')).then(|| rest[..end].split(';').collect())
+}
+
+/// The terminal name from the XTGETTCAP reply, ESC P 1 + r 544e=<hex> ESC \
+fn terminal_name(replies: &str) -> Option<String> {
+    let start = replies.find("\x1bP1+r544e=")? + 10;
+    let hex = &replies[start..start + replies[start..].find('\x1b')?];
+    // byte pairs below: anything else than hex digits isn't a name
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes: Option<Vec<u8>> = (0..hex.len() / 2)
+        .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok())
+        .collect();
+    Some(String::from_utf8_lossy(&bytes?).to_string())
+}
+
+fn protocol_from_replies(replies: &str) -> Protocol {
+    let name = terminal_name(replies).unwrap_or_default();
+    // WezTerm answers the kitty query but doesn't draw placeholders
+    if name.contains("WezTerm") || name.contains("iTerm") {
+        Protocol::Iterm2
+    } else if replies.contains("\x1b_Gi=31;OK") {
+        Protocol::Kitty
+    } else if da1_params(replies).is_some_and(|params| params.contains(&"4")) {
+        Protocol::Sixel
+    } else {
+        Protocol::HalfBlocks
+    }
+}
+
+/// An icon ready to be shown at a size
+#[derive(Debug)]
+enum Image {
+    /// ID of the image uploaded to kitty
+    Kitty(u32),
+    HalfBlocks(Vec<Line<'static>>),
+    /// Sixel or iTerm2 escape sequence
+    Sequence(String),
+}
+
+/// What the worker thread makes of a file: the pixels to upload for kitty, the rest is ready
+#[derive(Debug)]
+enum Decoded {
+    Pixels(RgbaImage),
+    Image(Image),
+}
+
+#[derive(Debug)]
+enum Entry {
+    /// Being decoded by the worker
+    Pending,
+    Failed,
+    /// With the frame it was last used in
+    Ready(Image, u64),
+}
+
+type Key = (PathBuf, u16, u16);
+
+/// Ready images kept, the least recently used ones go first
+const MAX_IMAGES: usize = 128;
+
+#[derive(Debug)]
+pub struct Icons {
+    /// By file and size in cells
+    images: HashMap<Key, Entry>,
+    requests: Option<Sender<Key>>,
+    results: Option<Receiver<(Key, Option<Decoded>)>>,
+    /// Kitty image IDs are `id_base | n`, n from 1 to 255
+    id_base: u32,
+    last_id: u8,
+    /// Frames drawn, for the LRU
+    frame: u64,
+    /// Sixel and iTerm2 images to print after this frame, and the ones printed after the last one
+    queued: Vec<(PathBuf, Rect)>,
+    shown: Vec<(PathBuf, Rect)>,
+    screen: Rect,
+}
+
+impl Icons {
+    pub fn new(protocol: Protocol) -> Self {
+        let cell = match crossterm::terminal::window_size() {
+            Ok(size) if size.width > 0 && size.columns > 0 && size.rows > 0 => {
+                (size.width as u32 / size.columns as u32, size.height as u32 / size.rows as u32)
+            },
+            _ => (8, 16),
+        };
+        // decoding and resizing large PNGs takes a while: it's done off the UI thread
+        let (requests, results) = if protocol != Protocol::None {
+            let (request_tx, request_rx) = mpsc::channel::<Key>();
+            let (result_tx, result_rx) = mpsc::channel();
+            thread::spawn(move || {
+                // the daemon is looked for once, its icons all come through the same connection
+                let mut daemon = IconClient::connect();
+                while let Ok(first) = request_rx.recv() {
+                    // the newest requests first, they're the rows on screen now
+                    let mut batch: Vec<Key> = std::iter::once(first).chain(request_rx.try_iter()).collect();
+                    while let Some(key) = batch.pop() {
+                        let decoded = decode(protocol, cell, &key, &mut daemon);
+                        if result_tx.send((key, decoded)).is_err() {
+                            return;
+                        }
+                        batch.extend(request_rx.try_iter());
+                    }
+                }
+            });
+            (Some(request_tx), Some(result_rx))
+        } else {
+            (None, None)
+        };
+        // IDs are global to the terminal window, and they go in the 24 bits of a colour:
+        // every fenrir gets its own 255 of them, from its pid
+        let id_base = (std::process::id() & 0xffff) << 8;
+        Self {
+            images: HashMap::new(),
+            requests,
+            results,
+            id_base,
+            last_id: 0,
+            frame: 0,
+            queued: vec![],
+            shown: vec![],
+            screen: Rect::default(),
+        }
+    }
+
+    /// Whether icons are still being decoded, to redraw soon
+    pub fn waiting(&self) -> bool {
+        self.images.values().any(|entry| matches!(entry, Entry::Pending))
+    }
+
+    /// Takes the icons decoded since the last frame, uploading the kitty ones
+    pub fn receive(&mut self) {
+        let Some(results) = &self.results else {
+            return;
+        };
+        let decoded: Vec<_> = results.try_iter().collect();
+        for (key, decoded) in decoded {
+            let entry = match decoded {
+                Some(Decoded::Pixels(img)) => match self.upload(&img, key.1.min(DIACRITICS.len() as u16), key.2.min(DIACRITICS.len() as u16)) {
+                    Some(id) => Entry::Ready(Image::Kitty(id), self.frame),
+                    None => Entry::Failed,
+                },
+                Some(Decoded::Image(image)) => Entry::Ready(image, self.frame),
+                None => Entry::Failed,
+            };
+            self.images.insert(key, entry);
+        }
+    }
+
+    /// Drops the least recently used images, and frees the kitty ones. Called once the frame is
+    /// drawn: the images it shows are never dropped, even when there are more than the cap
+    fn evict(&mut self) {
+        let mut commands = String::new();
+        for key in lru_victims(&self.images, self.frame, MAX_IMAGES) {
+            if let Some(Entry::Ready(Image::Kitty(id), _)) = self.images.remove(&key) {
+                commands.push_str(&format!("\x1b_Ga=d,d=I,i={},q=2\x1b\\", id));
+            }
+        }
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(commands.as_bytes()).and_then(|_| stdout.flush());
+    }
+
+    /// Asks the worker for the icon in `width`x`height` cells, if it isn't there yet
+    pub fn prefetch(&mut self, path: &Path, width: u16, height: u16) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        let key = (path.to_path_buf(), width, height);
+        if let Some(requests) = &self.requests
+            && !self.images.contains_key(&key)
+            && requests.send(key.clone()).is_ok() {
+            self.images.insert(key, Entry::Pending);
+        }
+    }
+
+    /// Whether the icon can be drawn in `width`x`height` cells: it's ready, or still being decoded
+    pub fn load(&mut self, path: &Path, width: u16, height: u16) -> bool {
+        self.prefetch(path, width, height);
+        matches!(self.images.get(&(path.to_path_buf(), width, height)), Some(Entry::Pending | Entry::Ready(..)))
+    }
+
+    fn image(&mut self, path: &Path, width: u16, height: u16) -> Option<&Image> {
+        self.prefetch(path, width, height);
+        match self.images.get_mut(&(path.to_path_buf(), width, height))? {
+            Entry::Ready(image, used) => {
+                *used = self.frame;
+                Some(image)
+            },
+            _ => None,
+        }
+    }
+
+    /// Rows of text showing the icon in `width`x`height` cells, None when it can't be drawn as text
+    /// or isn't ready yet
+    pub fn lines(&mut self, path: &Path, width: u16, height: u16) -> Option<Vec<Line<'static>>> {
+        match self.image(path, width, height)? {
+            Image::Kitty(id) => Some(placeholders(*id, width, height)),
+            Image::HalfBlocks(lines) => Some(lines.clone()),
+            Image::Sequence(_) => None,
+        }
+    }
+
+    /// Whether the icon is drawn as text, so it can go in a row of the list
+    pub fn inline(&mut self, path: &Path, width: u16, height: u16) -> bool {
+        !matches!(self.image(path, width, height), Some(Image::Sequence(_)))
+    }
+
+    /// Draws the icon over `area`, blank while it's decoded. Sixel and iTerm2 ones are queued for `flush`
+    pub fn render(&mut self, f: &mut Frame, path: &Path, area: Rect) -> bool {
+        // a corner icon doesn't fit a tiny terminal, drawing outside the buffer panics
+        let area = area.intersection(f.area());
+        if area.is_empty() || !self.load(path, area.width, area.height) {
+            return false;
+        }
+        f.render_widget(Clear, area);
+        match self.image(path, area.width, area.height) {
+            Some(Image::Sequence(_)) => {
+                // ratatui leaves these cells alone, so the image stays until something else is drawn there
+                for position in area.positions() {
+                    f.buffer_mut()[position].set_skip(true);
+                }
+                self.queued.push((path.to_path_buf(), area));
+            },
+            Some(_) => {
+                if let Some(lines) = self.lines(path, area.width, area.height) {
+                    f.render_widget(Paragraph::new(lines), area);
+                }
+            },
+            None => {},
+        }
+        true
+    }
+
+    /// Prints the queued images that aren't on screen yet. `buffer` is the frame just drawn
+    pub fn flush(&mut self, buffer: &Buffer) {
+        self.evict();
+        self.frame += 1;
+        let queued = std::mem::take(&mut self.queued);
+        // a resize clears the screen
+        if buffer.area != self.screen {
+            self.shown.clear();
+            self.screen = buffer.area;
+        }
+        let mut out = String::new();
+        let mut shown = vec![];
+        for (path, area) in queued {
+            // covered by something drawn later, like the failure panel
+            if !area.positions().all(|p| buffer[p].skip) {
+                continue;
+            }
+            if !self.shown.contains(&(path.clone(), area))
+                && let Some(Entry::Ready(Image::Sequence(sequence), _)) = self.images.get(&(path.clone(), area.width, area.height)) {
+                // the previous image could show through the transparent pixels: erase the cells (ECH) first
+                for y in area.top()..area.bottom() {
+                    out.push_str(&format!("\x1b[{};{}H\x1b[0m\x1b[{}X", y + 1, area.x + 1, area.width));
+                }
+                out.push_str(&format!("\x1b[{};{}H{}", area.y + 1, area.x + 1, sequence));
+            }
+            shown.push((path, area));
+        }
+        self.shown = shown;
+        if !out.is_empty() {
+            // the cursor goes back where ratatui left it
+            let mut stdout = std::io::stdout();
+            let _ = stdout.write_all(format!("\x1b7{}\x1b8", out).as_bytes()).and_then(|_| stdout.flush());
+        }
+    }
+
+    /// The next ID of our range that no uploaded image has, if there's one left
+    fn next_id(&mut self) -> u32 {
+        let used: HashSet<u32> = self.images.values()
+            .filter_map(|entry| match entry {
+                Entry::Ready(Image::Kitty(id), _) => Some(*id),
+                _ => None,
+            })
+            .collect();
+        for _ in 0..255 {
+            self.last_id = self.last_id % 255 + 1;
+            if !used.contains(&(self.id_base | self.last_id as u32)) {
+                break;
+            }
+        }
+        self.id_base | self.last_id as u32
+    }
+
+    fn upload(&mut self, img: &RgbaImage, width: u16, height: u16) -> Option<u32> {
+        let id = self.next_id();
+
+        let data = STANDARD.encode(img.as_raw());
+        let chunks: Vec<&[u8]> = data.as_bytes().chunks(CHUNK).collect();
+        let mut out = String::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let more = (i + 1 < chunks.len()) as u8;
+            let payload = std::str::from_utf8(chunk).unwrap_or_default();
+            if i == 0 {
+                // a=T: transmit and place, U=1: the placement is virtual, q=2: no replies on stdin
+                out.push_str(&format!("\x1b_Ga=T,q=2,f=32,s={},v={},i={},U=1,c={},r={},m={};{}\x1b\\",
+                    img.width(), img.height(), id, width, height, more, payload));
+            } else {
+                out.push_str(&format!("\x1b_Gm={};{}\x1b\\", more, payload));
+            }
+        }
+        let mut stdout = std::io::stdout();
+        stdout.write_all(out.as_bytes()).and_then(|_| stdout.flush()).ok()?;
+        Some(id)
+    }
+
+    /// Frees the images uploaded to kitty
+    pub fn delete_all(&mut self) {
+        let commands: String = self.images.drain()
+            .filter_map(|(_, entry)| match entry {
+                Entry::Ready(Image::Kitty(id), _) => Some(format!("\x1b_Ga=d,d=I,i={},q=2\x1b\\", id)),
+                _ => None,
+            })
+            .collect();
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(commands.as_bytes()).and_then(|_| stdout.flush());
+    }
+}
+
+/// The ready images to drop to get down to `max`, oldest first. The ones used in `frame` stay
+fn lru_victims(images: &HashMap<Key, Entry>, frame: u64, max: usize) -> Vec<Key> {
+    let total = images.values().filter(|entry| matches!(entry, Entry::Ready(..))).count();
+    if total <= max {
+        return vec![];
+    }
+    let mut old: Vec<(u64, &Key)> = images.iter()
+        .filter_map(|(key, entry)| match entry {
+            Entry::Ready(_, used) if *used < frame => Some((*used, key)),
+            _ => None,
+        })
+        .collect();
+    old.sort_unstable();
+    old.into_iter().take(total - max).map(|(_, key)| key.clone()).collect()
+}
+
+/// Loads and scales the icon for the protocol, on the worker thread
+fn decode(protocol: Protocol, (cell_width, cell_height): (u32, u32), (path, width, height): &Key, daemon: &mut Option<IconClient>) -> Option<Decoded> {
+    let (width, height) = (*width, *height);
+    let image = match protocol {
+        Protocol::Kitty => {
+            let width = width.min(DIACRITICS.len() as u16);
+            let height = height.min(DIACRITICS.len() as u16);
+            return Some(Decoded::Pixels(load_scaled(daemon, path, width as u32 * cell_width, height as u32 * cell_height)?));
+        },
+        Protocol::HalfBlocks => {
+            let img = load_scaled(daemon, path, width as u32, height as u32 * 2)?;
+            Image::HalfBlocks(half_blocks(&img, width, height))
+        },
+        Protocol::Sixel => {
+            // whole bands of 6 pixels, or the last one would spill over the cells below
+            let pixel_height = height as u32 * cell_height / 6 * 6;
+            let img = load_scaled(daemon, path, width as u32 * cell_width, pixel_height)?;
+            Image::Sequence(sixel(&img))
+        },
+        Protocol::Iterm2 => {
+            let img = load_scaled(daemon, path, width as u32 * cell_width, height as u32 * cell_height)?;
+            let mut png = std::io::Cursor::new(Vec::new());
+            img.write_to(&mut png, image::ImageFormat::Png).ok()?;
+            let png = png.into_inner();
+            Image::Sequence(format!("\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{}\x07",
+                png.len(), width, height, STANDARD.encode(&png)))
+        },
+        Protocol::None => return None,
+    };
+    Some(Decoded::Image(image))
+}
+
+/// The icon scaled to fit `width`x`height` pixels: from the daemon cache when there's one, else from the file.
+/// A broken daemon connection is dropped, the next icons are decoded here
+fn load_scaled(daemon: &mut Option<IconClient>, path: &Path, width: u32, height: u32) -> Option<RgbaImage> {
+    if let Some(client) = daemon {
+        match client.fetch(path, width, height) {
+            Ok(Some(img)) => return Some(img),
+            Ok(None) => {},
+            Err(e) => {
+                log_to_file(format!("Lost the daemon icon connection: {}", e));
+                *daemon = None;
+            },
+        }
+    }
+    match image::open(path) {
+        Ok(img) => Some(scale(&img, width, height)),
+        Err(e) => {
+            log_to_file(format!("Cannot load icon {}: {}", path.display(), e));
+            None
+        }
+    }
+}
+
+/// Keeps the aspect ratio, same as the daemon does for its cache
+pub fn scale(img: &DynamicImage, width: u32, height: u32) -> RgbaImage {
+    img.resize(width, height, image::imageops::FilterType::Triangle).to_rgba8()
+}
+
+/// Whether the span is a row of kitty placeholders, whose foreground is the image ID
+pub fn is_placeholder(span: &Span) -> bool {
+    span.content.starts_with(PLACEHOLDER)
+}
+
+/// Placeholder rows of a kitty image, the ID goes in the foreground colour
+fn placeholders(id: u32, width: u16, height: u16) -> Vec<Line<'static>> {
+    let width = width.min(DIACRITICS.len() as u16);
+    let height = height.min(DIACRITICS.len() as u16);
+    let style = Style::default().fg(Color::Rgb((id >> 16) as u8, (id >> 8) as u8, id as u8));
+    (0..height as usize)
+        .map(|row| {
+            let cells: String = (0..width as usize)
+                .map(|col| format!("{}{}{}", PLACEHOLDER, DIACRITICS[row], DIACRITICS[col]))
+                .collect();
+            Line::from(Span::styled(cells, style))
+        })
+        .collect()
+}
+
+/// Upper half block coloured like the top pixel on the background of the bottom one.
+/// Transparent pixels leave the background of the row
+fn half_blocks(img: &RgbaImage, width: u16, height: u16) -> Vec<Line<'static>> {
+    let pixel = |x: u32, y: u32| {
+        img.get_pixel_checked(x, y)
+            .filter(|p| p[3] >= 128)
+            .map(|p| Color::Rgb(p[0], p[1], p[2]))
+    };
+    (0..height as u32)
+        .map(|row| {
+            let spans: Vec<Span> = (0..width as u32)
+                .map(|x| match (pixel(x, row * 2), pixel(x, row * 2 + 1)) {
+                    (Some(top), Some(bottom)) => Span::styled("\u{2580}", Style::default().fg(top).bg(bottom)),
+                    (Some(top), None) => Span::styled("\u{2580}", Style::default().fg(top)),
+                    (None, Some(bottom)) => Span::styled("\u{2584}", Style::default().fg(bottom)),
+                    (None, None) => Span::raw(" "),
+                })
+                .collect();
+            Line::from(spans)
+        })
+        .collect()
+}
+
+/// Sixel image on a transparent background, with the colours reduced to a 6x6x6 cube
+fn sixel(img: &RgbaImage) -> String {
+    let (width, height) = img.dimensions();
+    let colours: Vec<Option<usize>> = img.pixels()
+        .map(|p| {
+            let level = |c: u8| (c as usize * 5 + 127) / 255;
+            (p[3] >= 128).then(|| level(p[0]) * 36 + level(p[1]) * 6 + level(p[2]))
+        })
+        .collect();
+    // P2=1: the pixels not drawn stay transparent
+    let mut out = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+    let mut used = [false; 216];
+    for c in colours.iter().flatten() {
+        used[*c] = true;
+    }
+    for (c, _) in used.iter().enumerate().filter(|(_, used)| **used) {
+        out.push_str(&format!("#{};2;{};{};{}", c, c / 36 * 20, c / 6 % 6 * 20, c % 6 * 20));
+    }
+    for band in (0..height).step_by(6) {
+        let mut in_band: Vec<usize> = (band..(band + 6).min(height))
+            .flat_map(|y| colours[(y * width) as usize..((y + 1) * width) as usize].iter().flatten().copied())
+            .collect();
+        in_band.sort_unstable();
+        in_band.dedup();
+        for (n, c) in in_band.iter().enumerate() {
+            if n > 0 {
+                // back to the start of the band for the next colour
+                out.push('$');
+            }
+            out.push_str(&format!("#{}", c));
+            let mut run: Option<(char, usize)> = None;
+            for x in 0..width {
+                let bits = (0..6)
+                    .filter(|dy| band + dy < height && colours[((band + dy) * width + x) as usize] == Some(*c))
+                    .fold(0, |bits, dy| bits | (1 << dy));
+                let sixel = char::from(63 + bits as u8);
+                run = match run {
+                    Some((s, count)) if s == sixel => Some((s, count + 1)),
+                    Some(previous) => {
+                        push_run(&mut out, previous);
+                        Some((sixel, 1))
+                    },
+                    None => Some((sixel, 1)),
+                };
+            }
+            if let Some(run) = run {
+                push_run(&mut out, run);
+            }
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+fn push_run(out: &mut String, (sixel, count): (char, usize)) {
+    if count > 3 {
+        out.push_str(&format!("!{}{}", count, sixel));
+    } else {
+        out.extend(std::iter::repeat_n(sixel, count));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replies to the detection queries: kitty graphics, XTGETTCAP TN, DA1
+    const KITTY: &str = "\x1b_Gi=31;OK\x1b\\\x1bP1+r544e=787465726d2d6b69747479\x1b\\\x1b[?62;c";
+    const FOOT: &str = "\x1bP1+r544e=666f6f74\x1b\\\x1b[?62;4;22c";
+    const XTERM: &str = "\x1bP0+r\x1b\\\x1b[?64;1;2;6;9;15;16;17;18;21;22;28c";
+    const XTERM_VT340: &str = "\x1bP0+r\x1b\\\x1b[?63;1;2;4;6;9;15;16;22;28c";
+    const WEZTERM: &str = "\x1b_Gi=31;OK\x1b\\\x1bP1+r544e=57657a5465726d\x1b\\\x1b[?65;4;6;18;22c";
+
+    #[test]
+    fn device_attributes() {
+        assert_eq!(da1_params(KITTY), Some(vec!["62", ""]));
+        assert_eq!(da1_params(FOOT), Some(vec!["62", "4", "22"]));
+        assert_eq!(da1_params(XTERM_VT340).unwrap()[3], "4");
+        // not complete yet
+        assert_eq!(da1_params("\x1b[?62;4;2"), None);
+        assert_eq!(da1_params(""), None);
+    }
+
+    #[test]
+    fn terminal_names() {
+        assert_eq!(terminal_name(KITTY).as_deref(), Some("xterm-kitty"));
+        assert_eq!(terminal_name(FOOT).as_deref(), Some("foot"));
+        assert_eq!(terminal_name(WEZTERM).as_deref(), Some("WezTerm"));
+        // xterm doesn't know the capability
+        assert_eq!(terminal_name(XTERM), None);
+        assert_eq!(terminal_name("\x1bP1+r544e=6\u{e9}6f\x1b\\"), None);
+        assert_eq!(terminal_name("\x1bP1+r544e=666f6"), None);
+    }
+
+    #[test]
+    fn protocols() {
+        assert_eq!(protocol_from_replies(KITTY), Protocol::Kitty);
+        assert_eq!(protocol_from_replies(FOOT), Protocol::Sixel);
+        assert_eq!(protocol_from_replies(XTERM), Protocol::HalfBlocks);
+        assert_eq!(protocol_from_replies(XTERM_VT340), Protocol::Sixel);
+        assert_eq!(protocol_from_replies(WEZTERM), Protocol::Iterm2);
+        assert_eq!(protocol_from_replies(""), Protocol::HalfBlocks);
+    }
+
+    fn entries(used: &[(&str, Option<u64>)]) -> HashMap<Key, Entry> {
+        used.iter()
+            .map(|(name, used)| {
+                let entry = match used {
+                    Some(frame) => Entry::Ready(Image::HalfBlocks(vec![]), *frame),
+                    None => Entry::Pending,
+                };
+                ((PathBuf::from(name), 2, 1), entry)
+            })
+            .collect()
+    }
+
+    fn names(keys: Vec<Key>) -> Vec<String> {
+        keys.into_iter().map(|(path, _, _)| path.to_string_lossy().to_string()).collect()
+    }
+
+    #[test]
+    fn least_recently_used_first() {
+        let images = entries(&[("a", Some(7)), ("b", Some(3)), ("c", Some(9)), ("d", Some(5)), ("e", None)]);
+        assert_eq!(names(lru_victims(&images, 9, 2)), ["b", "d"]);
+        assert_eq!(names(lru_victims(&images, 9, 3)), ["b"]);
+        // pending ones don't count
+        assert!(lru_victims(&images, 9, 4).is_empty());
+    }
+
+    #[test]
+    fn the_current_frame_is_kept() {
+        // a frame showing more icons than the cap: only the older ones go, nothing is decoded again
+        let images = entries(&[("a", Some(4)), ("b", Some(4)), ("c", Some(4)), ("d", Some(2))]);
+        assert_eq!(names(lru_victims(&images, 4, 2)), ["d"]);
+        let images = entries(&[("a", Some(4)), ("b", Some(4)), ("c", Some(4))]);
+        assert!(lru_victims(&images, 4, 1).is_empty());
+    }
+
+    #[test]
+    fn half_block_cells() {
+        let mut img = RgbaImage::new(2, 4);
+        img.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+        img.put_pixel(0, 1, image::Rgba([0, 0, 255, 255]));
+        img.put_pixel(1, 1, image::Rgba([0, 255, 0, 200]));
+        // too transparent to show
+        img.put_pixel(1, 0, image::Rgba([9, 9, 9, 100]));
+        let lines = half_blocks(&img, 2, 2);
+        assert_eq!(lines.len(), 2);
+        let top = &lines[0].spans;
+        assert_eq!(top[0].content, "\u{2580}");
+        assert_eq!(top[0].style, Style::default().fg(Color::Rgb(255, 0, 0)).bg(Color::Rgb(0, 0, 255)));
+        assert_eq!(top[1].content, "\u{2584}");
+        assert_eq!(top[1].style, Style::default().fg(Color::Rgb(0, 255, 0)));
+        assert!(lines[1].spans.iter().all(|span| span.content == " "));
+    }
+
+    #[test]
+    fn sixel_encoding() {
+        // a red row of 5 pixels and a transparent one, then a green pixel in the second band
+        let mut img = RgbaImage::new(6, 7);
+        for x in 0..5 {
+            img.put_pixel(x, 0, image::Rgba([255, 0, 0, 255]));
+        }
+        img.put_pixel(0, 6, image::Rgba([0, 255, 0, 255]));
+        assert_eq!(sixel(&img), concat!(
+            "\x1bP0;1;0q\"1;1;6;7",
+            "#30;2;0;100;0#180;2;100;0;0",
+            "#180!5@?-",
+            "#30@!5?-",
+            "\x1b\\",
+        ));
+    }
+
+    #[test]
+    fn clipped_to_the_frame() {
+        let mut terminal = ratatui::Terminal::new(ratatui::backend::TestBackend::new(10, 4)).unwrap();
+        let mut icons = Icons::new(Protocol::HalfBlocks);
+        terminal.draw(|f| {
+            assert!(icons.render(f, Path::new("/nonexistent.png"), Rect::new(0, 2, 14, 6)));
+            assert!(!icons.render(f, Path::new("/nonexistent.png"), Rect::new(12, 0, 14, 6)));
+        }).unwrap();
+    }
+
+    #[test]
+    fn kitty_ids_stay_in_range() {
+        let mut icons = Icons::new(Protocol::None);
+        icons.id_base = 0xffff << 8;
+        icons.images.insert((PathBuf::from("a.png"), 2, 1), Entry::Ready(Image::Kitty(0xffff02), 0));
+        assert_eq!(icons.next_id(), 0xffff01);
+        // the one in use is skipped
+        assert_eq!(icons.next_id(), 0xffff03);
+        for _ in 0..300 {
+            let id = icons.next_id();
+            assert!(id >> 8 == 0xffff && id & 0xff != 0 && id != 0xffff02, "{:x}", id);
+        }
+    }
+}
@@ -0,0 +1,358 @@
+//! Key bindings: chords like "ctrl+n" resolved to named actions, from a preset and the [keys] config
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
+
+use crate::config::KeysConfig;
+use crate::ui::Mode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    SelectNext,
+    SelectPrev,
+    PageDown,
+    PageUp,
+    SelectFirst,
+    SelectLast,
+    /// Left and right in the grid view, the cursor of the filter otherwise
+    SelectLeft,
+    SelectRight,
+    Launch,
+    /// Alt+Enter: SIGKILL, the containing folder, a terminal in the project
+    LaunchAlt,
+    /// Launches the nth (from 1) row visible in the list
+    LaunchNth(usize),
+    ToggleMode,
+    Mode(Mode),
+    /// Leaves the history, clears the marks, quits: the first that applies
+    Cancel,
+    Quit,
+    ToggleDetails,
+    ToggleView,
+    Mark,
+    EditArgs,
+    SaveGroup,
+    DeleteGroup,
+    ToggleSort,
+    /// Chooses the signal sent to the selected process
+    PickSignal,
+    OpenRemote,
+    HistorySearch,
+    NormalMode,
+    InsertMode,
+}
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        let action = match name {
+            "select-next" => Action::SelectNext,
+            "select-prev" => Action::SelectPrev,
+            "page-down" => Action::PageDown,
+            "page-up" => Action::PageUp,
+            "select-first" => Action::SelectFirst,
+            "select-last" => Action::SelectLast,
+            "select-left" => Action::SelectLeft,
+            "select-right" => Action::SelectRight,
+            "launch" => Action::Launch,
+            "launch-alt" => Action::LaunchAlt,
+            "toggle-mode" => Action::ToggleMode,
+            "cancel" => Action::Cancel,
+            "quit" => Action::Quit,
+            "toggle-details" => Action::ToggleDetails,
+            "toggle-view" => Action::ToggleView,
+            "mark" => Action::Mark,
+            "edit-args" => Action::EditArgs,
+            "save-group" => Action::SaveGroup,
+            "delete-group" => Action::DeleteGroup,
+            "toggle-sort" => Action::ToggleSort,
+            "pick-signal" => Action::PickSignal,
+            "open-remote" => Action::OpenRemote,
+            "history-search" => Action::HistorySearch,
+            "normal-mode" => Action::NormalMode,
+            "insert-mode" => Action::InsertMode,
+            _ => {
+                if let Some(n) = name.strip_prefix("launch-").and_then(|n| n.parse().ok())
+                    && (1..=9).contains(&n) {
+                    return Some(Action::LaunchNth(n));
+                }
+                return name.strip_prefix("mode-").and_then(Mode::from_name).map(Action::Mode);
+            },
+        };
+        Some(action)
+    }
+}
+
+type Chord = (KeyCode, KeyModifiers);
+
+/// Shift is part of the char itself ("G"), so it's dropped from char chords
+fn normalize(code: KeyCode, modifiers: KeyModifiers) -> Chord {
+    let modifiers = modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+    match code {
+        KeyCode::Char(_) => (code, modifiers - KeyModifiers::SHIFT),
+        _ => (code, modifiers),
+    }
+}
+
+fn parse_chord(chord: &str) -> Option<Chord> {
+    let mut parts: Vec<&str> = chord.split('+').collect();
+    let key = parts.pop()?;
+    let mut modifiers = KeyModifiers::NONE;
+    for part in parts {
+        modifiers |= match part.to_lowercase().as_str() {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" | "meta" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+    }
+    let mut chars = key.chars();
+    let code = match (chars.next(), chars.next()) {
+        // "shift+d" is the "D" the terminal sends. Shifted digits and symbols depend on the layout
+        (Some(c), None) if modifiers.contains(KeyModifiers::SHIFT) => match c.is_alphabetic() {
+            true => KeyCode::Char(c.to_uppercase().next()?),
+            false => return None,
+        },
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match key.to_lowercase().as_str() {
+            "enter" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "space" => KeyCode::Char(' '),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            f => KeyCode::F(f.strip_prefix('f')?.parse().ok()?),
+        },
+    };
+    Some(normalize(code, modifiers))
+}
+
+const COMMON: &[(&str, &str)] = &[
+    ("up", "select-prev"),
+    ("down", "select-next"),
+    ("pageup", "page-up"),
+    ("pagedown", "page-down"),
+    // Home/End are left to the cursor of the filter
+    ("ctrl+home", "select-first"),
+    ("ctrl+end", "select-last"),
+    ("left", "select-left"),
+    ("right", "select-right"),
+    ("enter", "launch"),
+    ("alt+enter", "launch-alt"),
+    ("tab", "edit-args"),
+    ("ctrl+c", "quit"),
+    ("f1", "mode-apps"),
+    ("f2", "mode-processes"),
+    ("f3", "mode-recent"),
+    ("f4", "mode-projects"),
+    ("f5", "mode-sessions"),
+    ("ctrl+t", "toggle-mode"),
+    ("alt+i", "toggle-details"),
+    ("ctrl+l", "toggle-view"),
+    ("space", "mark"),
+    ("ctrl+space", "mark"),
+    ("ctrl+s", "toggle-sort"),
+    ("alt+k", "pick-signal"),
+    ("ctrl+o", "open-remote"),
+    ("ctrl+r", "history-search"),
+    ("alt+1", "launch-1"),
+    ("alt+2", "launch-2"),
+    ("alt+3", "launch-3"),
+    ("alt+4", "launch-4"),
+    ("alt+5", "launch-5"),
+    ("alt+6", "launch-6"),
+    ("alt+7", "launch-7"),
+    ("alt+8", "launch-8"),
+    ("alt+9", "launch-9"),
+];
+
+const DEFAULT: &[(&str, &str)] = &[
+    ("esc", "cancel"),
+    ("ctrl+g", "save-group"),
+    ("ctrl+d", "delete-group"),
+];
+
+const EMACS: &[(&str, &str)] = &[
+    ("esc", "cancel"),
+    ("ctrl+g", "cancel"),
+    ("ctrl+n", "select-next"),
+    ("ctrl+p", "select-prev"),
+    ("ctrl+v", "page-down"),
+    ("alt+v", "page-up"),
+    ("alt+<", "select-first"),
+    ("alt+>", "select-last"),
+    ("ctrl+j", "launch"),
+    ("alt+g", "save-group"),
+    ("ctrl+d", "delete-group"),
+];
+
+/// Insert mode of the vim preset: typing goes to the filter
+const VIM_INSERT: &[(&str, &str)] = &[
+    ("esc", "normal-mode"),
+    ("ctrl+g", "save-group"),
+    ("ctrl+d", "delete-group"),
+];
+
+/// Normal mode of the vim preset: unbound keys do nothing
+const VIM_NORMAL: &[(&str, &str)] = &[
+    ("j", "select-next"),
+    ("k", "select-prev"),
+    ("ctrl+f", "page-down"),
+    ("ctrl+b", "page-up"),
+    ("ctrl+d", "page-down"),
+    ("ctrl+u", "page-up"),
+    ("g", "select-first"),
+    ("G", "select-last"),
+    ("h", "select-left"),
+    ("l", "select-right"),
+    ("i", "insert-mode"),
+    ("a", "insert-mode"),
+    ("/", "insert-mode"),
+    ("esc", "cancel"),
+    ("q", "quit"),
+    ("m", "mark"),
+    ("e", "edit-args"),
+    ("t", "toggle-mode"),
+    ("K", "toggle-details"),
+    ("v", "toggle-view"),
+    ("ctrl+g", "save-group"),
+    ("D", "delete-group"),
+    ("o", "open-remote"),
+    ("x", "pick-signal"),
+    ("1", "launch-1"),
+    ("2", "launch-2"),
+    ("3", "launch-3"),
+    ("4", "launch-4"),
+    ("5", "launch-5"),
+    ("6", "launch-6"),
+    ("7", "launch-7"),
+    ("8", "launch-8"),
+    ("9", "launch-9"),
+];
+
+#[derive(Debug, Default)]
+pub struct Keymap {
+    bindings: HashMap<Chord, Action>,
+    /// Normal mode bindings, only with the vim preset
+    normal: Option<HashMap<Chord, Action>>,
+}
+
+/// Preset bindings, then the config ones. Unknown chords and actions are reported in `errors`
+fn layer(presets: &[&[(&str, &str)]], config: &HashMap<String, String>, section: &str, errors: &mut Vec<String>) -> HashMap<Chord, Action> {
+    let mut bindings = HashMap::new();
+    for (chord, action) in presets.iter().flat_map(|p| p.iter()) {
+        match (parse_chord(chord), Action::from_name(action)) {
+            (Some(chord), Some(action)) => { bindings.insert(chord, action); },
+            _ => errors.push(format!("{}: the preset binding '{}' = '{}' is broken", section, chord, action)),
+        }
+    }
+    for (chord, action) in config {
+        let Some(parsed) = parse_chord(chord) else {
+            errors.push(format!("{}: unknown key '{}'", section, chord));
+            continue;
+        };
+        if action == "none" {
+            bindings.remove(&parsed);
+        } else if let Some(action) = Action::from_name(action) {
+            bindings.insert(parsed, action);
+        } else {
+            errors.push(format!("{}: unknown action '{}' for '{}'", section, action, chord));
+        }
+    }
+    bindings
+}
+
+impl Keymap {
+    pub fn new(config: &KeysConfig, errors: &mut Vec<String>) -> Self {
+        let (presets, normal): (&[&[(&str, &str)]], _) = match config.preset.as_str() {
+            "emacs" => (&[COMMON, EMACS], None),
+            "vim" => (&[COMMON, VIM_INSERT], Some(layer(&[COMMON, VIM_NORMAL], &config.normal, "keys.normal", errors))),
+            other => {
+                if other != "default" {
+                    errors.push(format!("keys.preset: unknown preset '{}', presets are default, emacs, vim", other));
+                }
+                (&[COMMON, DEFAULT], None)
+            },
+        };
+        Self { bindings: layer(presets, &config.bindings, "keys.bindings", errors), normal }
+    }
+
+    pub fn has_normal_mode(&self) -> bool {
+        self.normal.is_some()
+    }
+
+    /// The action bound to `key`, in normal or insert mode
+    pub fn resolve(&self, key: &KeyEvent, normal_mode: bool) -> Option<Action> {
+        let bindings = match &self.normal {
+            Some(normal) if normal_mode => normal,
+            _ => &self.bindings,
+        };
+        bindings.get(&normalize(key.code, key.modifiers)).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_parse() {
+        for preset in [COMMON, DEFAULT, EMACS, VIM_INSERT, VIM_NORMAL] {
+            for (chord, action) in preset {
+                assert!(parse_chord(chord).is_some(), "{}", chord);
+                assert!(Action::from_name(action).is_some(), "{}", action);
+            }
+        }
+        for preset in ["default", "emacs", "vim"] {
+            let mut errors = vec![];
+            Keymap::new(&KeysConfig { preset: preset.into(), ..Default::default() }, &mut errors);
+            assert!(errors.is_empty(), "{:?}", errors);
+        }
+    }
+
+    #[test]
+    fn chords() {
+        assert_eq!(parse_chord("ctrl+space"), Some((KeyCode::Char(' '), KeyModifiers::CONTROL)));
+        assert_eq!(parse_chord("alt+<"), Some((KeyCode::Char('<'), KeyModifiers::ALT)));
+        assert_eq!(parse_chord("f12"), Some((KeyCode::F(12), KeyModifiers::NONE)));
+        assert_eq!(parse_chord("D"), Some((KeyCode::Char('D'), KeyModifiers::NONE)));
+        assert_eq!(parse_chord("Ctrl+Shift+Home"), Some((KeyCode::Home, KeyModifiers::CONTROL | KeyModifiers::SHIFT)));
+        assert_eq!(parse_chord("hyper+a"), None);
+        assert_eq!(parse_chord("f"), Some((KeyCode::Char('f'), KeyModifiers::NONE)));
+        assert_eq!(parse_chord("fx"), None);
+    }
+
+    #[test]
+    fn home_and_end_move_the_cursor() {
+        let keymap = Keymap::new(&KeysConfig::default(), &mut vec![]);
+        assert_eq!(keymap.resolve(&KeyEvent::new(KeyCode::Home, KeyModifiers::NONE), false), None);
+        assert_eq!(keymap.resolve(&KeyEvent::new(KeyCode::End, KeyModifiers::NONE), false), None);
+        assert_eq!(keymap.resolve(&KeyEvent::new(KeyCode::Home, KeyModifiers::CONTROL), false), Some(Action::SelectFirst));
+        assert_eq!(keymap.resolve(&KeyEvent::new(KeyCode::End, KeyModifiers::CONTROL), false), Some(Action::SelectLast));
+    }
+
+    #[test]
+    fn shifted_chars() {
+        assert_eq!(parse_chord("shift+d"), parse_chord("D"));
+        assert_eq!(parse_chord("ctrl+shift+d"), Some((KeyCode::Char('D'), KeyModifiers::CONTROL)));
+        assert_eq!(parse_chord("shift+1"), None);
+
+        let config = KeysConfig {
+            bindings: [("shift+x".to_string(), "quit".to_string())].into(),
+            ..Default::default()
+        };
+        let keymap = Keymap::new(&config, &mut vec![]);
+        // what terminals send for Shift+X, with or without the modifier
+        assert_eq!(keymap.resolve(&KeyEvent::new(KeyCode::Char('X'), KeyModifiers::SHIFT), false), Some(Action::Quit));
+        assert_eq!(keymap.resolve(&KeyEvent::new(KeyCode::Char('X'), KeyModifiers::NONE), false), Some(Action::Quit));
+        assert_eq!(keymap.resolve(&KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE), false), None);
+    }
+}
@@ -0,0 +1,507 @@
+//! Launch backends: how a command line becomes a detached process
+
+use regex::Regex;
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use crate::activation;
+use crate::app::AppEntry;
+use crate::config::{expand_home, Config};
+use crate::niri::{self, NiriWindow, WorkspaceRef};
+use crate::utils::{log_to_file, shell_quote};
+
+pub const LAUNCH_LOG: &str = "/tmp/fenrir-launcher.log";
+
+#[derive(Debug, Clone)]
+pub struct LaunchRequest {
+    /// Shell command line, run with `sh -c`
+    pub command: String,
+    /// Desktop ID of the application, when launching one
+    pub app_id: Option<String>,
+    /// StartupWMClass, to recognize the app window
+    pub wm_class: Option<String>,
+    pub terminal: bool,
+    /// Path= of the desktop file
+    pub working_dir: Option<PathBuf>,
+    /// App name or command, for the UI
+    pub label: String,
+    /// niri workspace the window is moved to once it shows up
+    pub workspace: Option<WorkspaceRef>,
+    /// StartupNotify= of the desktop file
+    pub startup_notify: Option<bool>,
+}
+
+impl LaunchRequest {
+    pub fn for_app(app: &AppEntry) -> Self {
+        Self::for_app_with_args(app, "")
+    }
+
+    pub fn for_app_with_args(app: &AppEntry, args: &str) -> Self {
+        Self {
+            command: app.expand_exec(args),
+            app_id: Some(app.id.clone()),
+            wm_class: app.wm_class.clone(),
+            terminal: app.terminal,
+            working_dir: app.working_dir.clone(),
+            label: app.name.clone(),
+            workspace: None,
+            startup_notify: app.startup_notify,
+        }
+    }
+
+    /// The app with a command line edited by hand instead of its Exec
+    pub fn for_app_with_command(app: &AppEntry, command: &str) -> Self {
+        Self { command: command.to_string(), ..Self::for_app(app) }
+    }
+
+    pub fn command(exec: &str) -> Self {
+        Self {
+            command: exec.to_string(),
+            app_id: None,
+            wm_class: None,
+            terminal: false,
+            working_dir: None,
+            label: exec.to_string(),
+            workspace: None,
+            startup_notify: None,
+        }
+    }
+}
+
+impl LaunchRequest {
+    /// Whether a compositor window belongs to this launch
+    fn owns_window(&self, window: &NiriWindow, child_pid: u32) -> bool {
+        if window.pid.is_some_and(|pid| pid as u32 == child_pid) {
+            return true;
+        }
+        let Some(app_id) = &window.app_id else {
+            return false;
+        };
+        match (&self.app_id, &self.wm_class) {
+            (None, None) => true,
+            (id, wm_class) => {
+                // org.gnome.Nautilus -> Nautilus
+                let short_id = id.as_ref().and_then(|i| i.rsplit('.').next());
+                [id.as_deref(), wm_class.as_deref(), short_id].iter()
+                    .flatten()
+                    .any(|candidate| candidate.eq_ignore_ascii_case(app_id))
+            },
+        }
+    }
+}
+
+/// Why fenrir stopped waiting for the launched app
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ready {
+    /// A matching window appeared
+    Window(u64),
+    /// The child exited fine: it daemonized or handed over to an already running instance
+    Exited,
+    /// Still running, nothing else happened
+    Timeout,
+}
+
+/// What went wrong, shown in the error panel
+#[derive(Debug, Clone)]
+pub struct LaunchFailure {
+    pub request: LaunchRequest,
+    pub error: String,
+    /// Last lines written by the child on stdout/stderr
+    pub output: Vec<String>,
+}
+
+pub trait LaunchBackend: Send {
+    fn name(&self) -> &'static str;
+
+    /// Backends able to open a terminal window on their own get the bare command
+    fn handles_terminal(&self) -> bool {
+        false
+    }
+
+    fn command(&self, shell_command: &str, request: &LaunchRequest) -> Command;
+}
+
+/// `setsid sh -c`: the app becomes the leader of a new session
+pub struct Setsid;
+
+impl LaunchBackend for Setsid {
+    fn name(&self) -> &'static str {
+        "setsid"
+    }
+
+    fn command(&self, shell_command: &str, _request: &LaunchRequest) -> Command {
+        let mut cmd = Command::new("setsid");
+        cmd.arg("sh").arg("-c").arg(shell_command);
+        cmd
+    }
+}
+
+/// `systemd-run --user --scope`: one cgroup per app, output in `journalctl --user -u app-fenrir-*`
+pub struct SystemdRun;
+
+impl LaunchBackend for SystemdRun {
+    fn name(&self) -> &'static str {
+        "systemd"
+    }
+
+    fn command(&self, shell_command: &str, request: &LaunchRequest) -> Command {
+        let mut cmd = Command::new("setsid");
+        cmd.arg("systemd-run")
+            .args(["--user", "--scope", "--quiet", "--collect"])
+            .arg(format!("--unit={}", scope_unit_name(request)))
+            .arg("sh").arg("-c").arg(shell_command);
+        cmd
+    }
+}
+
+/// niri's `spawn` action: the compositor itself starts the app
+pub struct NiriSpawn;
+
+impl LaunchBackend for NiriSpawn {
+    fn name(&self) -> &'static str {
+        "niri"
+    }
+
+    fn command(&self, shell_command: &str, _request: &LaunchRequest) -> Command {
+        let mut cmd = Command::new("niri");
+        cmd.args(["msg", "action", "spawn", "--"]).arg("sh").arg("-c").arg(shell_command);
+        cmd
+    }
+}
+
+/// `kitty @ launch`: terminal apps in a new kitty OS window, needs remote control enabled
+pub struct KittyRemote;
+
+impl LaunchBackend for KittyRemote {
+    fn name(&self) -> &'static str {
+        "kitty"
+    }
+
+    fn handles_terminal(&self) -> bool {
+        true
+    }
+
+    fn command(&self, shell_command: &str, request: &LaunchRequest) -> Command {
+        let window_type = if request.terminal { "os-window" } else { "background" };
+        let mut cmd = Command::new("kitty");
+        cmd.args(["@", "launch"])
+            .arg(format!("--type={}", window_type))
+            .arg("sh").arg("-c").arg(shell_command);
+        cmd
+    }
+}
+
+pub fn backend_by_name(name: &str) -> Option<Box<dyn LaunchBackend>> {
+    match name {
+        "setsid" => Some(Box::new(Setsid)),
+        "systemd" | "systemd-run" => Some(Box::new(SystemdRun)),
+        "niri" => Some(Box::new(NiriSpawn)),
+        "kitty" => Some(Box::new(KittyRemote)),
+        _ => None,
+    }
+}
+
+/// Per-app backend first, then the terminal one for terminal apps, then the global one
+pub fn select_backend(config: &Config, request: &LaunchRequest) -> Box<dyn LaunchBackend> {
+    let name = request.app_id.as_deref()
+        .and_then(|id| config.app(id))
+        .and_then(|a| a.backend.clone())
+        .or_else(|| config.launch.terminal_backend.clone().filter(|_| request.terminal))
+        .unwrap_or_else(|| config.launch.backend.clone());
+    backend_by_name(&name).unwrap_or_else(|| {
+        log_to_file(format!("Unknown launch backend '{}', using setsid", name));
+        Box::new(Setsid)
+    })
+}
+
+/// Runs the command prepared by `build_shell_command`, with the activation token in front.
+/// Getting the token is a compositor roundtrip, so this runs on the launch thread
+fn spawn(backend: &dyn LaunchBackend, shell_command: &str, request: &LaunchRequest) -> io::Result<Child> {
+    // fenrir's own token is stale for anybody else, the app gets a fresh one.
+    // Apps with StartupNotify=false declare they don't use it
+    let mut token_prefix = "unset XDG_ACTIVATION_TOKEN DESKTOP_STARTUP_ID; ".to_string();
+    if request.startup_notify != Some(false)
+        && let Some(token) = activation::startup_token(request.app_id.as_deref()) {
+        let token = shell_quote(&token);
+        token_prefix.push_str(&format!("export XDG_ACTIVATION_TOKEN={} DESKTOP_STARTUP_ID={}; ", token, token));
+    }
+    let shell_command = format!("{}{}", token_prefix, shell_command);
+    log_to_file(format!("Launching '{}' with {}", shell_command, backend.name()));
+
+    // Log file in caso di errori
+    let log_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(LAUNCH_LOG)
+        .unwrap_or_else(|_| std::fs::File::create("/dev/null").unwrap());
+
+    backend.command(&shell_command, request)
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(log_file.try_clone()?))
+        .stderr(Stdio::from(log_file))
+        .spawn()
+}
+
+/// The command line run by the backend: environment changes and working directory first,
+/// then the wrappers and the app command, inside a terminal if needed. The activation
+/// token is added in front of it by `spawn`.
+/// Everything happens in the shell so it works the same with every backend, even
+/// with the ones (niri, kitty) that don't start the app as our child
+fn build_shell_command(config: &Config, request: &LaunchRequest, backend: &dyn LaunchBackend) -> String {
+    let app_config = request.app_id.as_deref().and_then(|id| config.app(id));
+    let valid_name = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+    let mut prefix = String::new();
+
+    let unset = config.launch.unset_env.iter()
+        .chain(app_config.iter().flat_map(|a| a.unset_env.iter()));
+    for name in unset {
+        if valid_name.is_match(name) {
+            prefix.push_str(&format!("unset {}; ", name));
+        } else {
+            log_to_file(format!("Ignoring invalid variable name '{}'", name));
+        }
+    }
+    if let Some(app_config) = app_config {
+        let mut env: Vec<_> = app_config.env.iter().collect();
+        env.sort();
+        for (name, value) in env {
+            if valid_name.is_match(name) {
+                prefix.push_str(&format!("export {}={}; ", name, shell_quote(value)));
+            } else {
+                log_to_file(format!("Ignoring invalid variable name '{}'", name));
+            }
+        }
+    }
+
+    // the configured directory wins over the Path= key of the desktop file
+    let working_dir = app_config.and_then(|a| a.cwd.as_deref()).map(expand_home)
+        .or_else(|| request.working_dir.clone());
+    if let Some(dir) = working_dir {
+        prefix.push_str(&format!("cd {} && ", shell_quote(&dir.to_string_lossy())));
+    }
+
+    let mut command = request.command.clone();
+    if let Some(app_config) = app_config {
+        if let Some(args) = &app_config.args {
+            command = format!("{} {}", command, args);
+        }
+        if !app_config.wrapper.is_empty() {
+            let wrapper: Vec<String> = app_config.wrapper.iter().map(|w| shell_quote(w)).collect();
+            command = format!("{} {}", wrapper.join(" "), command);
+        }
+    }
+
+    // Terminal=true apps need a window to run in: fenrir's own terminal is going away
+    if request.terminal && !backend.handles_terminal() {
+        format!("{}{}", prefix, config.in_terminal(&command))
+    } else {
+        format!("{}{}", prefix, command)
+    }
+}
+
+/// A spawned app watched until it's ready: a new matching window shows up (niri only), or the
+/// child exits successfully, or the timeout expires. A non-zero exit status in the meantime is a
+/// failure. Without niri there's no window to wait for, so the timeout is the `grace_ms` period.
+/// The UI polls it between frames, so it keeps drawing and reading keys
+pub struct Launch {
+    request: LaunchRequest,
+    /// The child, sent by the launch thread once the activation token is there and it's spawned
+    spawned: Receiver<io::Result<Child>>,
+    child: Option<Child>,
+    /// Windows already open before the launch, they don't count
+    known_windows: Option<HashSet<u64>>,
+    timeout: Duration,
+    started: Instant,
+    last_window_check: Instant,
+    exited: bool,
+    log_offset: u64,
+}
+
+fn failure(request: &LaunchRequest, log_offset: u64, error: String) -> Box<LaunchFailure> {
+    Box::new(LaunchFailure {
+        request: request.clone(),
+        error,
+        output: read_log_tail(log_offset, 10),
+    })
+}
+
+impl Launch {
+    pub fn start(config: &Config, request: &LaunchRequest) -> Result<Self, Box<LaunchFailure>> {
+        let log_offset = std::fs::metadata(LAUNCH_LOG).map(|m| m.len()).unwrap_or(0);
+        let known_windows: Option<HashSet<u64>> = if niri::available() {
+            niri::windows().map(|w| w.iter().map(|w| w.id).collect())
+        } else {
+            None
+        };
+        // without a way to see windows, only the grace period is left
+        let timeout = Duration::from_millis(if known_windows.is_some() { config.launch.ready_timeout_ms } else { config.launch.grace_ms });
+        let backend = select_backend(config, request);
+        let shell_command = build_shell_command(config, request, backend.as_ref());
+        let (tx, spawned) = channel();
+        let thread_request = request.clone();
+        std::thread::Builder::new()
+            .name("launch".into())
+            .spawn(move || {
+                let _ = tx.send(spawn(backend.as_ref(), &shell_command, &thread_request));
+            })
+            .map_err(|e| failure(request, log_offset, e.to_string()))?;
+        Ok(Self {
+            request: request.clone(),
+            spawned,
+            child: None,
+            known_windows,
+            timeout,
+            started: Instant::now(),
+            last_window_check: Instant::now(),
+            exited: false,
+            log_offset,
+        })
+    }
+
+    pub fn request(&self) -> &LaunchRequest {
+        &self.request
+    }
+
+    /// The spawned child, to be reaped by whoever outlives the launch
+    pub fn into_child(self) -> Option<Child> {
+        self.child
+    }
+
+    /// None while the app isn't ready and the timeout hasn't expired
+    pub fn poll(&mut self) -> Option<Result<Ready, Box<LaunchFailure>>> {
+        if self.child.is_none() {
+            match self.spawned.try_recv() {
+                Ok(Ok(child)) => {
+                    self.child = Some(child);
+                    // waiting for the token doesn't count
+                    self.started = Instant::now();
+                },
+                Ok(Err(e)) => return Some(Err(failure(&self.request, self.log_offset, e.to_string()))),
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => return Some(Err(failure(&self.request, self.log_offset, "the launch thread died".into()))),
+            }
+        }
+        let Some(child) = &mut self.child else {
+            return None;
+        };
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => {
+                // a handed over launch (kitty, niri, single instance apps) can still show its window
+                if self.request.workspace.is_none() || self.known_windows.is_none() {
+                    return Some(Ok(Ready::Exited));
+                }
+                self.exited = true;
+            },
+            Ok(Some(status)) => {
+                log_to_file(format!("'{}' {}", self.request.command, status));
+                return Some(Err(failure(&self.request, self.log_offset, format!("exited with {}", status))));
+            },
+            Ok(None) => {},
+            Err(e) => return Some(Err(failure(&self.request, self.log_offset, e.to_string()))),
+        }
+
+        if let Some(known) = &self.known_windows
+            && self.last_window_check.elapsed() >= Duration::from_millis(100) {
+            self.last_window_check = Instant::now();
+            let child_pid = child.id();
+            let new_window = niri::windows().unwrap_or_default().into_iter()
+                .find(|w| !known.contains(&w.id) && self.request.owns_window(w, child_pid));
+            if let Some(window) = new_window {
+                log_to_file(format!("'{}' ready after {:?}", self.request.command, self.started.elapsed()));
+                if let Some(workspace) = &self.request.workspace
+                    && let Err(e) = niri::move_window_to_workspace(window.id, workspace) {
+                    log_to_file(format!("Cannot move '{}' to workspace {}: {}", self.request.label, workspace, e));
+                }
+                return Some(Ok(Ready::Window(window.id)));
+            }
+        }
+
+        if self.started.elapsed() < self.timeout {
+            return None;
+        }
+        if let Some(workspace) = &self.request.workspace {
+            log_to_file(format!("No window of '{}' seen, not moved to workspace {}", self.request.label, workspace));
+        }
+        Some(Ok(if self.exited { Ready::Exited } else { Ready::Timeout }))
+    }
+}
+
+/// Last `lines` lines appended to the launch log after `offset`
+fn read_log_tail(offset: u64, lines: usize) -> Vec<String> {
+    let mut contents = String::new();
+    if let Ok(mut file) = std::fs::File::open(LAUNCH_LOG)
+        && file.seek(SeekFrom::Start(offset)).is_ok() {
+        let _ = file.read_to_string(&mut contents);
+    }
+    let all: Vec<String> = contents.lines().map(|l| l.to_string()).collect();
+    all[all.len().saturating_sub(lines)..].to_vec()
+}
+
+/// app-fenrir-<ID>-<RANDOM>.scope, as the systemd desktop environments convention asks
+fn scope_unit_name(request: &LaunchRequest) -> String {
+    let id = match &request.app_id {
+        Some(id) => id.clone(),
+        None => request.command.split_whitespace().next()
+            .and_then(|c| c.rsplit('/').next())
+            .unwrap_or("command")
+            .to_string(),
+    };
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    format!("app-fenrir-{}-{:x}.scope", systemd_escape(&id), nanos ^ std::process::id())
+}
+
+/// Same escaping as `systemd-escape`
+fn systemd_escape(value: &str) -> String {
+    value.chars().enumerate()
+        .map(|(i, c)| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | ':' | '_' => c.to_string(),
+            '.' if i > 0 => c.to_string(),
+            _ => {
+                let mut buf = [0u8; 4];
+                c.encode_utf8(&mut buf).bytes().map(|b| format!("\\x{:02x}", b)).collect()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    #[test]
+    fn escape_like_systemd() {
+        assert_eq!(systemd_escape("org.gnome.Nautilus"), "org.gnome.Nautilus");
+        assert_eq!(systemd_escape("my-app"), "my\\x2dapp");
+        assert_eq!(systemd_escape(".hidden"), "\\x2ehidden");
+        assert_eq!(systemd_escape("a b/c"), "a\\x20b\\x2fc");
+        assert_eq!(systemd_escape("caff\u{e8}"), "caff\\xc3\\xa8");
+    }
+
+    #[test]
+    fn backend_selection() {
+        let mut config = Config::default();
+        config.launch.backend = "systemd".into();
+        config.launch.terminal_backend = Some("kitty".into());
+        config.apps.insert("org.gnome.Nautilus.desktop".into(), AppConfig { backend: Some("niri".into()), ..Default::default() });
+
+        let mut request = LaunchRequest::command("firefox");
+        assert_eq!(select_backend(&config, &request).name(), "systemd");
+        request.terminal = true;
+        assert_eq!(select_backend(&config, &request).name(), "kitty");
+        // the per-app backend wins, desktop IDs match with or without ".desktop"
+        request.app_id = Some("org.gnome.Nautilus".into());
+        assert_eq!(select_backend(&config, &request).name(), "niri");
+
+        config.launch.backend = "bogus".into();
+        assert_eq!(select_backend(&config, &LaunchRequest::command("firefox")).name(), "setsid");
+    }
+}
@@ -0,0 +1,345 @@
+//! Readline-like editing of the filter row, and the history of the queries
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::ops::Deref;
+use std::path::PathBuf;
+
+use crate::history::state_dir;
+use crate::utils::save_json;
+
+const MAX_QUERIES: usize = 200;
+/// Changes that can be undone, the oldest ones are forgotten
+const MAX_UNDO: usize = 100;
+
+#[derive(Debug, Default)]
+pub struct LineEditor {
+    text: String,
+    /// Byte offset, always on a char boundary
+    cursor: usize,
+    /// Text and cursor before each change
+    undo: Vec<(String, usize)>,
+    /// Consecutive typed chars are undone together
+    typing: bool,
+}
+
+impl Deref for LineEditor {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl LineEditor {
+    pub fn new(text: &str) -> Self {
+        Self { text: text.to_string(), cursor: text.len(), ..Default::default() }
+    }
+
+    /// The text before the cursor, to place it on screen
+    pub fn before_cursor(&self) -> &str {
+        &self.text[..self.cursor]
+    }
+
+    fn snapshot(&mut self) {
+        if self.undo.len() >= MAX_UNDO {
+            self.undo.remove(0);
+        }
+        self.undo.push((self.text.clone(), self.cursor));
+    }
+
+    /// Replaces the whole text, undoable
+    pub fn set(&mut self, text: &str) {
+        self.snapshot();
+        self.typing = false;
+        self.text = text.to_string();
+        self.cursor = self.text.len();
+    }
+
+    pub fn insert_str(&mut self, s: &str) {
+        self.snapshot();
+        self.typing = false;
+        self.text.insert_str(self.cursor, s);
+        self.cursor += s.len();
+    }
+
+    fn insert_char(&mut self, c: char) {
+        if !self.typing {
+            self.snapshot();
+            self.typing = true;
+        }
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    fn prev_boundary(&self, from: usize) -> usize {
+        self.text[..from].char_indices().next_back().map(|(i, _)| i).unwrap_or(0)
+    }
+
+    fn next_boundary(&self, from: usize) -> usize {
+        self.text[from..].chars().next().map(|c| from + c.len_utf8()).unwrap_or(from)
+    }
+
+    /// Start of the word before the cursor
+    fn word_start(&self) -> usize {
+        let before = &self.text[..self.cursor];
+        let trimmed = before.trim_end_matches(|c: char| !is_word(c));
+        trimmed.trim_end_matches(is_word).len()
+    }
+
+    /// End of the word after the cursor
+    fn word_end(&self) -> usize {
+        let after = &self.text[self.cursor..];
+        let skipped = after.trim_start_matches(|c: char| !is_word(c));
+        let rest = skipped.trim_start_matches(is_word);
+        self.text.len() - rest.len()
+    }
+
+    /// Removes the text between `from` and `to`, undoable
+    fn delete(&mut self, from: usize, to: usize) -> bool {
+        if from == to {
+            return false;
+        }
+        self.snapshot();
+        self.typing = false;
+        self.text.replace_range(from..to, "");
+        self.cursor = from;
+        true
+    }
+
+    fn undo(&mut self) -> bool {
+        self.typing = false;
+        match self.undo.pop() {
+            Some((text, cursor)) => {
+                self.text = text;
+                self.cursor = cursor;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Applies an editing key. None if the key isn't an editing one, otherwise whether the text changed
+    pub fn handle_key(&mut self, key: &KeyEvent) -> Option<bool> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        let moved = |editor: &mut Self, to: usize| {
+            editor.cursor = to;
+            editor.typing = false;
+            Some(false)
+        };
+        match key.code {
+            KeyCode::Char('a') if ctrl => moved(self, 0),
+            KeyCode::Char('e') if ctrl => moved(self, self.text.len()),
+            KeyCode::Char('b') if alt => moved(self, self.word_start()),
+            KeyCode::Char('f') if alt => moved(self, self.word_end()),
+            KeyCode::Char('w') if ctrl => Some(self.delete(self.word_start(), self.cursor)),
+            KeyCode::Char('u') if ctrl => Some(self.delete(0, self.cursor)),
+            KeyCode::Char('k') if ctrl => Some(self.delete(self.cursor, self.text.len())),
+            KeyCode::Char('d') if alt => Some(self.delete(self.cursor, self.word_end())),
+            // Ctrl+_ is reported as Ctrl+7 by some terminals
+            KeyCode::Char('z' | '_' | '7') if ctrl => Some(self.undo()),
+            KeyCode::Char(c) if !ctrl && !alt => {
+                self.insert_char(c);
+                Some(true)
+            },
+            KeyCode::Backspace if alt || ctrl => Some(self.delete(self.word_start(), self.cursor)),
+            KeyCode::Backspace => Some(self.delete(self.prev_boundary(self.cursor), self.cursor)),
+            KeyCode::Delete => Some(self.delete(self.cursor, self.next_boundary(self.cursor))),
+            KeyCode::Left if ctrl || alt => moved(self, self.word_start()),
+            KeyCode::Right if ctrl || alt => moved(self, self.word_end()),
+            KeyCode::Left => moved(self, self.prev_boundary(self.cursor)),
+            KeyCode::Right => moved(self, self.next_boundary(self.cursor)),
+            KeyCode::Home => moved(self, 0),
+            KeyCode::End => moved(self, self.text.len()),
+            _ => None,
+        }
+    }
+}
+
+/// Past queries, oldest first, browsed with Up/Down and searched with Ctrl+R
+#[derive(Debug, Default)]
+pub struct QueryHistory {
+    queries: Vec<String>,
+    /// Entry being shown while browsing
+    position: Option<usize>,
+    /// What was typed when browsing started: only entries containing it are shown
+    pattern: String,
+}
+
+impl QueryHistory {
+    fn path() -> Option<PathBuf> {
+        state_dir().map(|d| d.join("queries.json"))
+    }
+
+    pub fn load() -> Self {
+        let queries = Self::path()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default();
+        Self { queries, ..Default::default() }
+    }
+
+    pub fn record(&mut self, query: &str) {
+        let query = query.trim();
+        if query.is_empty() {
+            return;
+        }
+        self.queries.retain(|q| q != query);
+        self.queries.push(query.to_string());
+        let excess = self.queries.len().saturating_sub(MAX_QUERIES);
+        self.queries.drain(..excess);
+
+        if let Some(path) = Self::path() {
+            save_json(&path, &self.queries, "queries");
+        }
+    }
+
+    pub fn browsing(&self) -> bool {
+        self.position.is_some()
+    }
+
+    pub fn stop(&mut self) {
+        self.position = None;
+    }
+
+    fn matches(&self, i: usize) -> bool {
+        self.queries[i].to_lowercase().contains(&self.pattern.to_lowercase())
+    }
+
+    /// The previous matching query, `current` is the pattern when browsing starts
+    pub fn older(&mut self, current: &str) -> Option<&str> {
+        if self.position.is_none() {
+            self.pattern = current.to_string();
+        }
+        let end = self.position.unwrap_or(self.queries.len());
+        let found = (0..end).rev().find(|i| self.matches(*i))?;
+        self.position = Some(found);
+        Some(&self.queries[found])
+    }
+
+    /// The next matching query, or the pattern itself past the newest one
+    pub fn newer(&mut self) -> Option<&str> {
+        let start = self.position? + 1;
+        match (start..self.queries.len()).find(|i| self.matches(*i)) {
+            Some(found) => {
+                self.position = Some(found);
+                Some(&self.queries[found])
+            },
+            None => {
+                self.position = None;
+                Some(&self.pattern)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    fn type_text(editor: &mut LineEditor, text: &str) {
+        for c in text.chars() {
+            editor.handle_key(&key(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+    }
+
+    /// Editor with the cursor at the `|` of `text`
+    fn at(text: &str) -> LineEditor {
+        let cursor = text.find('|').unwrap();
+        LineEditor { text: text.replace('|', ""), cursor, ..Default::default() }
+    }
+
+    #[test]
+    fn words_with_punctuation() {
+        let editor = at("foo.bar-baz|");
+        assert_eq!(&editor.text[editor.word_start()..], "baz");
+        let editor = at("foo.bar-|baz");
+        assert_eq!(&editor.text[editor.word_start()..editor.cursor], "bar-");
+        let editor = at("|--foo_bar baz");
+        assert_eq!(&editor.text[..editor.word_end()], "--foo_bar");
+        let editor = at("a.b |");
+        assert_eq!(editor.word_start(), 2);
+        let editor = at("a |  ");
+        assert_eq!(editor.word_end(), editor.text.len());
+    }
+
+    #[test]
+    fn words_with_multibyte_chars() {
+        let mut editor = at("caf\u{e9} cr\u{e8}me br\u{fb}l\u{e9}e|");
+        assert_eq!(&editor.text[editor.word_start()..], "br\u{fb}l\u{e9}e");
+        editor.handle_key(&key(KeyCode::Char('w'), KeyModifiers::CONTROL));
+        assert_eq!(&*editor, "caf\u{e9} cr\u{e8}me ");
+        editor.handle_key(&key(KeyCode::Left, KeyModifiers::CONTROL));
+        assert_eq!(editor.before_cursor(), "caf\u{e9} ");
+        editor.handle_key(&key(KeyCode::Left, KeyModifiers::NONE));
+        editor.handle_key(&key(KeyCode::Left, KeyModifiers::NONE));
+        assert_eq!(editor.before_cursor(), "caf");
+        editor.handle_key(&key(KeyCode::Delete, KeyModifiers::NONE));
+        assert_eq!(&*editor, "caf cr\u{e8}me ");
+        editor.handle_key(&key(KeyCode::Char('d'), KeyModifiers::ALT));
+        assert_eq!(&*editor, "caf ");
+    }
+
+    #[test]
+    fn undo_groups_typing() {
+        let mut editor = LineEditor::new("");
+        type_text(&mut editor, "hello");
+        editor.handle_key(&key(KeyCode::Left, KeyModifiers::NONE));
+        type_text(&mut editor, "XY");
+        editor.handle_key(&key(KeyCode::Backspace, KeyModifiers::NONE));
+        assert_eq!(&*editor, "hellXo");
+
+        let undo = key(KeyCode::Char('z'), KeyModifiers::CONTROL);
+        assert_eq!(editor.handle_key(&undo), Some(true));
+        assert_eq!(&*editor, "hellXYo");
+        editor.handle_key(&undo);
+        assert_eq!(&*editor, "hello");
+        assert_eq!(editor.before_cursor(), "hell");
+        editor.handle_key(&undo);
+        assert_eq!(&*editor, "");
+        assert_eq!(editor.handle_key(&undo), Some(false));
+    }
+
+    #[test]
+    fn undo_is_capped() {
+        let mut editor = LineEditor::new("");
+        for i in 0..MAX_UNDO + 10 {
+            editor.set(&i.to_string());
+        }
+        assert_eq!(editor.undo.len(), MAX_UNDO);
+        while editor.undo() {}
+        assert_eq!(&*editor, "9");
+    }
+
+    #[test]
+    fn history_with_a_pattern() {
+        let mut history = QueryHistory {
+            queries: ["firefox", "fish", "files", "gimp", "Firefox beta"].iter().map(|q| q.to_string()).collect(),
+            ..Default::default()
+        };
+        assert_eq!(history.older("fi"), Some("Firefox beta"));
+        // the pattern is kept while browsing, whatever the filter shows
+        assert_eq!(history.older("Firefox beta"), Some("files"));
+        assert_eq!(history.older(""), Some("fish"));
+        assert_eq!(history.older(""), Some("firefox"));
+        assert_eq!(history.older(""), None);
+        assert_eq!(history.newer(), Some("fish"));
+        assert_eq!(history.newer(), Some("files"));
+        assert_eq!(history.newer(), Some("Firefox beta"));
+        // past the newest one: back to what was typed
+        assert_eq!(history.newer(), Some("fi"));
+        assert!(!history.browsing());
+        assert_eq!(history.newer(), None);
+
+        assert_eq!(history.older("xyz"), None);
+        assert!(!history.browsing());
+    }
+}
@@ -2,15 +2,34 @@
 
 // kitty -e ~/.config/niri/fenrir
 
+mod activation;
 mod app;
+mod config;
 // mod sysinfo;
 mod data;
 mod ui;
 mod utils;
+mod daemon;
 mod data_sources;
+mod groups;
+mod history;
+mod icons;
+mod keymap;
+mod launcher;
+mod lineedit;
+mod mime;
+mod niri;
+mod presets;
+mod processes;
+mod projects;
+mod recent;
+mod sessions;
+mod theme;
+mod websearch;
 
 // use crate::app::AppEntry;
-use crate::ui::run_ui;
+use crate::icons::Protocol;
+use crate::ui::{run_ui, Mode};
 use std::env;
 use std::error::Error;
 
@@ -18,20 +37,62 @@ use std::time::Instant;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let t0 = Instant::now();
+    // read before anything else: it's only good for a launch soon after startup
+    activation::init();
     let args: Vec<String> = env::args().collect();
-    let force_icons = args.contains(&"--force-icons".to_string());
-    let no_icons = args.contains(&"--no-icons".to_string());
-
-    let show_icons = if force_icons {
-        true
-    } else if no_icons {
-        false
+    let arg_value = |name: &str| args.iter()
+        .position(|a| a == name)
+        .and_then(|i| args.get(i + 1))
+        .cloned();
+    // --icons kitty|sixel|iterm2|halfblocks|none, asked to the terminal otherwise
+    let icons = if args.contains(&"--force-icons".to_string()) {
+        Some(Protocol::Kitty)
+    } else if args.contains(&"--no-icons".to_string()) {
+        Some(Protocol::None)
+    } else if let Some(name) = arg_value("--icons") {
+        let Some(protocol) = Protocol::from_name(&name) else {
+            eprintln!("fenrir: unknown --icons '{}', use kitty, sixel, iterm2, halfblocks or none", name);
+            std::process::exit(2);
+        };
+        Some(protocol)
     } else {
-        std::env::var("KITTY_WINDOW_ID").is_ok()
+        None
+    };
+    // --mode apps|processes|recent|projects|sessions
+    let mode_name = arg_value("--mode");
+    let query = arg_value("--query");
+    let mode = match mode_name.as_deref() {
+        Some(name) => match Mode::from_name(name) {
+            Some(mode) => mode,
+            None => {
+                eprintln!("fenrir: unknown --mode '{}', use apps, processes, recent, projects or sessions", name);
+                std::process::exit(2);
+            },
+        },
+        None => Mode::Apps,
     };
+    // --open <file-or-url> lists the apps that can open it
+    let open = arg_value("--open").map(|target| match mime::local_path(&target) {
+        // the app may be started in another directory
+        Some(_) if target.starts_with("file://") => target,
+        Some(path) => std::fs::canonicalize(&path).map(|p| p.to_string_lossy().to_string()).unwrap_or(target),
+        None => target,
+    });
+
+    let config = config::load_config();
+    if args.contains(&"--daemon".to_string()) {
+        daemon::run_daemon(&config)?;
+        return Ok(());
+    }
+    // --show asks the daemon to bring up the UI, or to update the attached one
+    if args.contains(&"--show".to_string())
+        && daemon::send_request(&daemon::Request::Show { mode: mode_name, query: query.clone() }) {
+        return Ok(());
+    }
 
     // let apps_empty: Vec<AppEntry> = vec![];
     // let apps = app::load_app_entries()?;
-    run_ui(show_icons, t0)?;
+    let mode = if open.is_some() { Mode::Apps } else { mode };
+    run_ui(&config, icons, mode, query.as_deref().unwrap_or_default(), open.as_deref(), t0)?;
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,440 @@
+//! MIME type detection and the default/associated apps of the mimeapps.list layers
+//! (https://specifications.freedesktop.org/mime-apps-spec/latest/)
+
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::app::AppEntry;
+use crate::utils::{data_home, xdg_home};
+
+/// The local path of a target given as path or file:// URI, None for the other URLs
+pub fn local_path(target: &str) -> Option<PathBuf> {
+    if let Some(rest) = target.strip_prefix("file://") {
+        // file://host/path: the host is usually empty or localhost
+        let path = rest.trim_start_matches("localhost");
+        return Some(PathBuf::from(percent_decode_str(path).decode_utf8_lossy().to_string()));
+    }
+    match url_scheme(target) {
+        Some(_) => None,
+        None => Some(PathBuf::from(target)),
+    }
+}
+
+fn url_scheme(target: &str) -> Option<String> {
+    let scheme_re = Regex::new(r"^([a-zA-Z][a-zA-Z0-9+.-]*):").unwrap();
+    let scheme = scheme_re.captures(target)?[1].to_lowercase();
+    // "notes:today.md" is more likely a file than a URL
+    if target[scheme.len() + 1..].starts_with("//") || ["mailto", "tel", "magnet", "sms"].contains(&scheme.as_str()) {
+        Some(scheme)
+    } else {
+        None
+    }
+}
+
+/// MIME type of a path or URL: URL schemes become x-scheme-handler/<scheme>,
+/// files are recognized by extension first and by their first bytes then
+pub fn detect(target: &str) -> String {
+    let path = match local_path(target) {
+        Some(path) => path,
+        None => return format!("x-scheme-handler/{}", url_scheme(target).unwrap_or_default()),
+    };
+    if path.is_dir() {
+        return "inode/directory".into();
+    }
+    by_extension(&path)
+        .map(|m| m.to_string())
+        .or_else(|| by_content(&path))
+        .unwrap_or_else(|| "application/octet-stream".into())
+}
+
+fn by_extension(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    let mime = match ext.as_str() {
+        "txt" | "log" => "text/plain",
+        "md" | "markdown" => "text/markdown",
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "csv" => "text/csv",
+        "rs" => "text/rust",
+        "py" => "text/x-python",
+        "c" | "h" => "text/x-csrc",
+        "cpp" | "hpp" | "cc" => "text/x-c++src",
+        "sh" | "bash" => "application/x-shellscript",
+        "js" => "application/javascript",
+        "ts" => "text/x-typescript",
+        "json" => "application/json",
+        "toml" => "application/toml",
+        "yaml" | "yml" => "application/x-yaml",
+        "xml" => "application/xml",
+        "pdf" => "application/pdf",
+        "epub" => "application/epub+zip",
+        "odt" => "application/vnd.oasis.opendocument.text",
+        "ods" => "application/vnd.oasis.opendocument.spreadsheet",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "xz" => "application/x-xz",
+        "zst" => "application/zstd",
+        "7z" => "application/x-7z-compressed",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "bmp" => "image/bmp",
+        "ico" => "image/vnd.microsoft.icon",
+        "mp3" => "audio/mpeg",
+        "ogg" | "oga" => "audio/ogg",
+        "flac" => "audio/flac",
+        "wav" => "audio/x-wav",
+        "mp4" | "m4v" => "video/mp4",
+        "mkv" => "video/x-matroska",
+        "webm" => "video/webm",
+        "avi" => "video/x-msvideo",
+        "mov" => "video/quicktime",
+        "torrent" => "application/x-bittorrent",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+fn by_content(path: &Path) -> Option<String> {
+    let mut head = [0u8; 512];
+    let len = std::fs::File::open(path).and_then(|mut f| f.read(&mut head)).ok()?;
+    let head = &head[..len];
+    let magic: &[(&[u8], usize, &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", 0, "image/png"),
+        (b"\xff\xd8\xff", 0, "image/jpeg"),
+        (b"GIF8", 0, "image/gif"),
+        (b"WEBP", 8, "image/webp"),
+        (b"%PDF-", 0, "application/pdf"),
+        (b"PK\x03\x04", 0, "application/zip"),
+        (b"\x1f\x8b", 0, "application/gzip"),
+        (b"\xfd7zXZ\x00", 0, "application/x-xz"),
+        (b"\x28\xb5\x2f\xfd", 0, "application/zstd"),
+        (b"7z\xbc\xaf\x27\x1c", 0, "application/x-7z-compressed"),
+        (b"ustar", 257, "application/x-tar"),
+        (b"\x7fELF", 0, "application/x-executable"),
+        (b"ID3", 0, "audio/mpeg"),
+        (b"fLaC", 0, "audio/flac"),
+        (b"OggS", 0, "audio/ogg"),
+        (b"ftyp", 4, "video/mp4"),
+        (b"\x1a\x45\xdf\xa3", 0, "video/x-matroska"),
+        (b"#!", 0, "application/x-shellscript"),
+    ];
+    if let Some((_, _, mime)) = magic.iter().find(|(bytes, offset, _)| head.get(*offset..*offset + bytes.len()) == Some(bytes)) {
+        return Some(mime.to_string());
+    }
+    // no NUL bytes and valid UTF-8 (a multibyte char can be cut at the end)
+    let text = !head.contains(&0) && match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    text.then(|| "text/plain".to_string())
+}
+
+/// Type whose apps can open `mime` too, when nothing handles it directly
+pub fn fallback(mime: &str) -> Option<&'static str> {
+    let textual = ["application/json", "application/toml", "application/x-yaml", "application/xml",
+        "application/javascript", "application/x-shellscript"];
+    if mime != "text/plain" && (mime.starts_with("text/") || textual.contains(&mime)) {
+        Some("text/plain")
+    } else {
+        None
+    }
+}
+
+/// The mimeapps.list and mimeinfo.cache layers, most important first.
+/// Apps are desktop IDs with the ".desktop" suffix
+#[derive(Debug, Default)]
+pub struct MimeApps {
+    /// [Default Applications] of each layer
+    defaults: Vec<HashMap<String, Vec<String>>>,
+    added: HashMap<String, Vec<String>>,
+    removed: HashMap<String, Vec<String>>,
+    /// [MIME Cache] of the applications directories
+    cache: HashMap<String, Vec<String>>,
+}
+
+fn env_dirs(var: &str, default: &str) -> Vec<PathBuf> {
+    std::env::var(var).ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| default.to_string())
+        .split(':')
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Sections of an ini-like file: section -> key -> ';' separated values
+fn parse_sections(contents: &str) -> HashMap<String, HashMap<String, Vec<String>>> {
+    let mut sections: HashMap<String, HashMap<String, Vec<String>>> = HashMap::new();
+    let mut current = String::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = name.to_string();
+        } else if let Some((key, value)) = line.split_once('=') {
+            let values = value.split(';').map(str::trim).filter(|v| !v.is_empty()).map(|v| v.to_string()).collect();
+            sections.entry(current.clone()).or_default().insert(key.trim().to_string(), values);
+        }
+    }
+    sections
+}
+
+impl MimeApps {
+    pub fn load() -> Self {
+        let desktops: Vec<String> = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default()
+            .split(':')
+            .filter(|d| !d.is_empty())
+            .map(|d| d.to_lowercase())
+            .collect();
+        let config_dirs = xdg_home("XDG_CONFIG_HOME", ".config").into_iter()
+            .chain(env_dirs("XDG_CONFIG_DIRS", "/etc/xdg"));
+        let data_dirs: Vec<PathBuf> = data_home().into_iter()
+            .chain(env_dirs("XDG_DATA_DIRS", "/usr/local/share:/usr/share"))
+            .map(|d| d.join("applications"))
+            .collect();
+
+        // in each directory the desktop specific file comes before the generic one
+        let mut lists = vec![];
+        for dir in config_dirs.chain(data_dirs.iter().cloned()) {
+            lists.extend(desktops.iter().map(|d| dir.join(format!("{}-mimeapps.list", d))));
+            lists.push(dir.join("mimeapps.list"));
+        }
+
+        let mut apps = Self::default();
+        for list in lists {
+            if let Ok(contents) = std::fs::read_to_string(&list) {
+                apps.add_list(&contents);
+            }
+        }
+        for dir in &data_dirs {
+            if let Ok(contents) = std::fs::read_to_string(dir.join("mimeinfo.cache")) {
+                apps.add_cache(&contents);
+            }
+        }
+        apps
+    }
+
+    /// A mimeapps.list, less important than the ones added before
+    fn add_list(&mut self, contents: &str) {
+        let mut sections = parse_sections(contents);
+        self.defaults.push(sections.remove("Default Applications").unwrap_or_default());
+        for (mime, ids) in sections.remove("Added Associations").unwrap_or_default() {
+            self.added.entry(mime).or_default().extend(ids);
+        }
+        for (mime, ids) in sections.remove("Removed Associations").unwrap_or_default() {
+            self.removed.entry(mime).or_default().extend(ids);
+        }
+    }
+
+    fn add_cache(&mut self, contents: &str) {
+        for (mime, ids) in parse_sections(contents).remove("MIME Cache").unwrap_or_default() {
+            self.cache.entry(mime).or_default().extend(ids);
+        }
+    }
+
+    /// The default app: the first installed one of the most important layer that has any
+    fn default_app(&self, mime: &str, installed: impl Fn(&str) -> bool) -> Option<String> {
+        self.defaults.iter()
+            .filter_map(|layer| layer.get(mime))
+            .flat_map(|ids| ids.iter())
+            .find(|id| installed(id))
+            .cloned()
+    }
+
+    /// Associated apps, added associations first, without the removed ones
+    pub fn associated(&self, mime: &str) -> Vec<String> {
+        let removed = self.removed.get(mime).cloned().unwrap_or_default();
+        let mut result: Vec<String> = vec![];
+        for id in self.added.get(mime).into_iter().chain(self.cache.get(mime)).flatten() {
+            if !removed.contains(id) && !result.contains(id) {
+                result.push(id.clone());
+            }
+        }
+        result
+    }
+
+    fn is_removed(&self, mime: &str, id: &str) -> bool {
+        self.removed.get(mime).is_some_and(|ids| ids.iter().any(|i| i == id))
+    }
+
+    /// Indexes in `apps` of the installed apps able to open `mime`: the default one,
+    /// the associated ones, then the ones declaring it in MimeType=
+    pub fn handlers(&self, mime: &str, apps: &[AppEntry]) -> Vec<usize> {
+        let find = |id: &str| apps.iter().position(|a| a.file_name() == id);
+        let mut result: Vec<usize> = vec![];
+        let default = self.default_app(mime, |id| find(id).is_some());
+        for id in default.iter().chain(self.associated(mime).iter()) {
+            if let Some(i) = find(id)
+                && !result.contains(&i) {
+                result.push(i);
+            }
+        }
+        for (i, app) in apps.iter().enumerate() {
+            if app.mime_types.iter().any(|m| m == mime)
+                && !self.is_removed(mime, &app.file_name())
+                && !result.contains(&i) {
+                result.push(i);
+            }
+        }
+        match fallback(mime) {
+            Some(parent) if result.is_empty() => self.handlers(parent, apps),
+            _ => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER_LIST: &str = "\
+# user choices
+[Default Applications]
+text/plain=missing.desktop;gedit.desktop;
+image/png=eog.desktop
+
+[Added Associations]
+image/png=gimp.desktop;
+
+[Removed Associations]
+image/png=firefox.desktop;
+";
+
+    const SYSTEM_LIST: &str = "\
+[Default Applications]
+text/plain=vim.desktop
+application/pdf=evince.desktop
+";
+
+    const CACHE: &str = "\
+[MIME Cache]
+image/png=firefox.desktop;eog.desktop;inkscape.desktop;
+text/plain=vim.desktop;
+";
+
+    fn apps() -> Vec<AppEntry> {
+        let app = |id: &str, mime_types: &[&str]| AppEntry {
+            id: id.into(),
+            name: id.into(),
+            mime_types: mime_types.iter().map(|m| m.to_string()).collect(),
+            ..Default::default()
+        };
+        vec![
+            app("gedit", &[]),
+            app("vim", &["text/plain"]),
+            app("eog", &[]),
+            app("gimp", &[]),
+            app("firefox", &["image/png"]),
+            app("inkscape", &[]),
+            app("krita", &["image/png"]),
+            app("evince", &["application/pdf"]),
+        ]
+    }
+
+    fn mime_apps() -> MimeApps {
+        let mut mime_apps = MimeApps::default();
+        mime_apps.add_list(USER_LIST);
+        mime_apps.add_list(SYSTEM_LIST);
+        mime_apps.add_cache(CACHE);
+        mime_apps
+    }
+
+    fn handler_ids(mime: &str) -> Vec<String> {
+        let apps = apps();
+        mime_apps().handlers(mime, &apps).into_iter().map(|i| apps[i].id.clone()).collect()
+    }
+
+    #[test]
+    fn sections() {
+        let sections = parse_sections(USER_LIST);
+        assert_eq!(sections["Default Applications"]["text/plain"], ["missing.desktop", "gedit.desktop"]);
+        assert_eq!(sections["Removed Associations"]["image/png"], ["firefox.desktop"]);
+        assert_eq!(sections.len(), 3);
+    }
+
+    #[test]
+    fn default_app_of_the_first_layer() {
+        let mime_apps = mime_apps();
+        let installed = |id: &str| id != "missing.desktop";
+        // the user list wins, skipping what isn't installed
+        assert_eq!(mime_apps.default_app("text/plain", installed).as_deref(), Some("gedit.desktop"));
+        assert_eq!(mime_apps.default_app("application/pdf", installed).as_deref(), Some("evince.desktop"));
+        assert_eq!(mime_apps.default_app("video/mp4", installed), None);
+    }
+
+    #[test]
+    fn handlers_order() {
+        // default, added, cached without the removed one, then MimeType=
+        assert_eq!(handler_ids("image/png"), ["eog", "gimp", "inkscape", "krita"]);
+        assert_eq!(handler_ids("text/plain"), ["gedit", "vim"]);
+        // nothing opens Rust sources, the text editors do
+        assert_eq!(handler_ids("text/rust"), ["gedit", "vim"]);
+        assert!(handler_ids("video/mp4").is_empty());
+    }
+
+    #[test]
+    fn extensions() {
+        assert_eq!(by_extension(Path::new("a/Photo.JPG")), Some("image/jpeg"));
+        assert_eq!(by_extension(Path::new("notes.md")), Some("text/markdown"));
+        assert_eq!(by_extension(Path::new("archive.tar.gz")), Some("application/gzip"));
+        assert_eq!(by_extension(Path::new("Makefile")), None);
+        assert_eq!(by_extension(Path::new("file.unknown")), None);
+    }
+
+    #[test]
+    fn magic_bytes() {
+        let dir = std::env::temp_dir().join(format!("fenrir-mime-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut tar = vec![0u8; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        let files: &[(&str, &[u8], Option<&str>)] = &[
+            ("png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", Some("image/png")),
+            ("webp", b"RIFF\x10\0\0\0WEBPVP8 ", Some("image/webp")),
+            ("tar", &tar, Some("application/x-tar")),
+            ("script", b"#!/bin/sh\necho hi\n", Some("application/x-shellscript")),
+            ("text", "caf\u{e9} cr\u{e8}me".as_bytes(), Some("text/plain")),
+            // a multibyte char cut by the 512 bytes limit is still text
+            ("cut", &[b"a".repeat(511).as_slice(), "\u{e9}".as_bytes()].concat(), Some("text/plain")),
+            ("binary", b"\x01\x02\0\x03", None),
+        ];
+        for (name, contents, mime) in files {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            assert_eq!(by_content(&path).as_deref(), *mime, "{}", name);
+        }
+        assert_eq!(detect(&dir.join("binary").to_string_lossy()), "application/octet-stream");
+        assert_eq!(detect(&dir.to_string_lossy()), "inode/directory");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn urls_and_paths() {
+        assert_eq!(detect("https://example.com/a.png"), "x-scheme-handler/https");
+        assert_eq!(detect("mailto:someone@example.com"), "x-scheme-handler/mailto");
+        assert_eq!(local_path("file:///home/me/My%20File.txt"), Some(PathBuf::from("/home/me/My File.txt")));
+        assert_eq!(local_path("file://localhost/tmp/a"), Some(PathBuf::from("/tmp/a")));
+        assert_eq!(local_path("notes:today.md"), Some(PathBuf::from("notes:today.md")));
+        assert_eq!(local_path("https://example.com"), None);
+    }
+
+    #[test]
+    fn target_arg_for_field_codes() {
+        let app = |exec: &str| AppEntry { exec: exec.into(), ..Default::default() };
+        // %f wants a local path, %u takes the URI as it is
+        assert_eq!(app("gimp %f").target_arg("file:///tmp/My%20Pic.png"), "'/tmp/My Pic.png'");
+        assert_eq!(app("firefox %u").target_arg("file:///tmp/My%20Pic.png"), "'file:///tmp/My%20Pic.png'");
+        assert_eq!(app("firefox %U").target_arg("https://example.com/?a=1&b=2"), "'https://example.com/?a=1&b=2'");
+        assert_eq!(app("gimp %F").target_arg("it's.png"), "'it'\\''s.png'");
+        // a URL can't become a path: passed as it is
+        assert_eq!(app("vlc %f").target_arg("https://example.com/v.mp4"), "'https://example.com/v.mp4'");
+    }
+}
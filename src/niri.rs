@@ -0,0 +1,75 @@
+//! Minimal niri IPC client, talking JSON over $NIRI_SOCKET
+
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NiriWindow {
+    pub id: u64,
+    pub app_id: Option<String>,
+    pub pid: Option<i32>,
+}
+
+/// Workspace by index (1-based, on the focused output) or by name
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum WorkspaceRef {
+    Index(u8),
+    Name(String),
+}
+
+impl WorkspaceRef {
+    fn to_json(&self) -> Value {
+        match self {
+            WorkspaceRef::Index(i) => json!({ "Index": i }),
+            WorkspaceRef::Name(n) => json!({ "Name": n }),
+        }
+    }
+}
+
+impl std::fmt::Display for WorkspaceRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WorkspaceRef::Index(i) => write!(f, "{}", i),
+            WorkspaceRef::Name(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+pub fn available() -> bool {
+    std::env::var("NIRI_SOCKET").is_ok_and(|s| !s.is_empty())
+}
+
+/// Sends a single request and returns the content of the "Ok" reply
+fn request(req: &Value) -> io::Result<Value> {
+    let path = std::env::var("NIRI_SOCKET").map_err(|_| io::Error::new(io::ErrorKind::NotFound, "NIRI_SOCKET not set"))?;
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(Duration::from_millis(500)))?;
+    writeln!(stream, "{}", req)?;
+    stream.flush()?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let reply: Value = serde_json::from_str(&line)?;
+    match reply.get("Ok") {
+        Some(ok) => Ok(ok.clone()),
+        None => Err(io::Error::other(format!("niri: {}", reply.get("Err").unwrap_or(&reply)))),
+    }
+}
+
+pub fn windows() -> Option<Vec<NiriWindow>> {
+    let reply = request(&json!("Windows")).ok()?;
+    serde_json::from_value(reply.get("Windows")?.clone()).ok()
+}
+
+fn action(action: Value) -> io::Result<()> {
+    request(&json!({ "Action": action })).map(|_| ())
+}
+
+/// Moves the window without following it
+pub fn move_window_to_workspace(window_id: u64, workspace: &WorkspaceRef) -> io::Result<()> {
+    action(json!({ "MoveWindowToWorkspace": { "window_id": window_id, "reference": workspace.to_json(), "focus": false } }))
+}
@@ -0,0 +1,48 @@
+//! Command presets: the command lines edited before a launch, remembered per app
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::history::state_dir;
+use crate::utils::save_json;
+
+const MAX_PRESETS: usize = 10;
+
+#[derive(Debug, Default)]
+pub struct ArgPresets {
+    /// desktop ID -> command lines, most recent first
+    apps: HashMap<String, Vec<String>>,
+}
+
+impl ArgPresets {
+    fn path() -> Option<PathBuf> {
+        state_dir().map(|d| d.join("command_presets.json"))
+    }
+
+    pub fn load() -> Self {
+        let apps = Self::path()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default();
+        Self { apps }
+    }
+
+    pub fn for_app(&self, app_id: &str) -> &[String] {
+        self.apps.get(app_id).map(|p| p.as_slice()).unwrap_or_default()
+    }
+
+    pub fn remember(&mut self, app_id: &str, command: &str) {
+        let command = command.trim();
+        if command.is_empty() {
+            return;
+        }
+        let presets = self.apps.entry(app_id.to_string()).or_default();
+        presets.retain(|p| p != command);
+        presets.insert(0, command.to_string());
+        presets.truncate(MAX_PRESETS);
+
+        if let Some(path) = Self::path() {
+            save_json(&path, &self.apps, "presets");
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Process viewer: reads /proc, samples CPU usage between refreshes and sends signals
+
+use std::collections::HashMap;
+use std::fs;
+use std::time::{Duration, Instant};
+
+const REFRESH_EVERY: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: i32,
+    pub name: String,
+    pub user: String,
+    pub cpu: f64,
+    pub rss: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Cpu,
+    Memory,
+}
+
+pub struct ProcessList {
+    pub procs: Vec<ProcessInfo>,
+    pub sort: SortKey,
+    pub mem_total: u64,
+    prev_ticks: HashMap<i32, u64>,
+    prev_total: u64,
+    cpus: usize,
+    /// Names by uid, /etc/passwd is read at the first refresh
+    users: Option<HashMap<u32, String>>,
+    page_size: u64,
+    last_refresh: Option<Instant>,
+}
+
+impl ProcessList {
+    pub fn new() -> Self {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        Self {
+            procs: vec![],
+            sort: SortKey::Cpu,
+            mem_total: read_mem_total(),
+            prev_ticks: HashMap::new(),
+            prev_total: 0,
+            cpus: 1,
+            users: None,
+            page_size: if page_size > 0 { page_size as u64 } else { 4096 },
+            last_refresh: None,
+        }
+    }
+
+    pub fn refresh_if_needed(&mut self) {
+        if self.last_refresh.is_none_or(|t| t.elapsed() >= REFRESH_EVERY) {
+            self.refresh();
+        }
+    }
+
+    pub fn toggle_sort(&mut self) {
+        self.sort = match self.sort {
+            SortKey::Cpu => SortKey::Memory,
+            SortKey::Memory => SortKey::Cpu,
+        };
+        self.sort_procs();
+    }
+
+    pub fn refresh(&mut self) {
+        let (total, cpus) = read_cpu_total();
+        let total_delta = total.saturating_sub(self.prev_total);
+        self.cpus = cpus.max(1);
+        let users = self.users.get_or_insert_with(read_users);
+
+        let mut procs = vec![];
+        let mut ticks = HashMap::new();
+        if let Ok(dir) = fs::read_dir("/proc") {
+            for entry in dir.flatten() {
+                let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse::<i32>().ok()) else {
+                    continue;
+                };
+                // both gone when the process exits while it's read
+                let (Some((name, proc_ticks)), Some(uid)) = (read_stat(pid), read_uid(pid)) else {
+                    continue;
+                };
+                // processes seen for the first time have no previous sample: 0% until next refresh
+                let cpu = match self.prev_ticks.get(&pid) {
+                    Some(prev) if total_delta > 0 => {
+                        proc_ticks.saturating_sub(*prev) as f64 / total_delta as f64 * 100.0 * self.cpus as f64
+                    },
+                    _ => 0.0,
+                };
+                ticks.insert(pid, proc_ticks);
+                procs.push(ProcessInfo {
+                    pid,
+                    name,
+                    user: users.get(&uid).cloned().unwrap_or_else(|| uid.to_string()),
+                    cpu,
+                    rss: read_rss_pages(pid).unwrap_or(0) * self.page_size,
+                });
+            }
+        }
+
+        self.prev_ticks = ticks;
+        self.prev_total = total;
+        self.procs = procs;
+        self.sort_procs();
+        self.last_refresh = Some(Instant::now());
+    }
+
+    fn sort_procs(&mut self) {
+        match self.sort {
+            SortKey::Cpu => self.procs.sort_by(|a, b| b.cpu.total_cmp(&a.cpu).then(b.rss.cmp(&a.rss))),
+            SortKey::Memory => self.procs.sort_by(|a, b| b.rss.cmp(&a.rss).then(b.cpu.total_cmp(&a.cpu))),
+        }
+    }
+
+    pub fn filtered(&self, filter: &str) -> Vec<&ProcessInfo> {
+        let filter = filter.to_lowercase();
+        self.procs.iter()
+            .filter(|p| p.name.to_lowercase().contains(&filter) || p.user.to_lowercase().contains(&filter) || p.pid.to_string().starts_with(&filter))
+            .collect()
+    }
+}
+
+pub fn send_signal(pid: i32, signal: i32) -> std::io::Result<()> {
+    if unsafe { libc::kill(pid, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// The signals offered by the signal picker, in this order
+pub const SIGNALS: [i32; 9] = [
+    libc::SIGTERM,
+    libc::SIGKILL,
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGSTOP,
+    libc::SIGCONT,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+];
+
+pub fn signal_name(signal: i32) -> &'static str {
+    match signal {
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGSTOP => "SIGSTOP",
+        libc::SIGCONT => "SIGCONT",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGUSR2 => "SIGUSR2",
+        _ => "signal",
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    let mib = bytes as f64 / 1024.0 / 1024.0;
+    if mib >= 1024.0 {
+        format!("{:.1}G", mib / 1024.0)
+    } else {
+        format!("{:.0}M", mib)
+    }
+}
+
+fn read_cpu_total() -> (u64, usize) {
+    let Ok(stat) = fs::read_to_string("/proc/stat") else {
+        return (0, 1);
+    };
+    let mut total = 0;
+    let mut cpus = 0;
+    for line in stat.lines() {
+        if let Some(rest) = line.strip_prefix("cpu ") {
+            total = rest.split_whitespace().filter_map(|v| v.parse::<u64>().ok()).sum();
+        } else if line.starts_with("cpu") {
+            cpus += 1;
+        }
+    }
+    (total, cpus)
+}
+
+fn read_stat(pid: i32) -> Option<(String, u64)> {
+    parse_stat(&fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?)
+}
+
+/// Returns the process name and utime + stime
+fn parse_stat(stat: &str) -> Option<(String, u64)> {
+    // comm may contain spaces and parentheses: it goes from the first '(' to the last ')'
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = stat[open + 1..close].to_string();
+    let fields: Vec<&str> = stat[close + 1..].split_whitespace().collect();
+    let utime = fields.get(11)?.parse::<u64>().ok()?;
+    let stime = fields.get(12)?.parse::<u64>().ok()?;
+    Some((name, utime + stime))
+}
+
+fn read_uid(pid: i32) -> Option<u32> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    status.lines()
+        .find_map(|l| l.strip_prefix("Uid:"))
+        .and_then(|l| l.split_whitespace().next())
+        .and_then(|uid| uid.parse().ok())
+}
+
+fn read_rss_pages(pid: i32) -> Option<u64> {
+    let statm = fs::read_to_string(format!("/proc/{}/statm", pid)).ok()?;
+    statm.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn read_mem_total() -> u64 {
+    fs::read_to_string("/proc/meminfo").ok()
+        .and_then(|m| m.lines()
+            .find_map(|l| l.strip_prefix("MemTotal:"))
+            .and_then(|l| l.split_whitespace().next())
+            .and_then(|kb| kb.parse::<u64>().ok()))
+        .map(|kb| kb * 1024)
+        .unwrap_or(0)
+}
+
+fn read_users() -> HashMap<u32, String> {
+    let mut users = HashMap::new();
+    if let Ok(passwd) = fs::read_to_string("/etc/passwd") {
+        for line in passwd.lines() {
+            let parts: Vec<&str> = line.split(':').collect();
+            if parts.len() > 2 && let Ok(uid) = parts[2].parse::<u32>() {
+                users.insert(uid, parts[0].to_string());
+            }
+        }
+    }
+    users
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stat_line() {
+        let stat = "1234 (bash) S 1 1234 1234 34816 1234 4194304 1030 0 0 0 17 5 0 0 20 0 1 0 12345 9125888 1234 18446744073709551615";
+        assert_eq!(parse_stat(stat), Some(("bash".to_string(), 22)));
+    }
+
+    #[test]
+    fn stat_comm_with_spaces_and_parentheses() {
+        let stat = "42 (Web Content (1)) R 1 42 42 0 -1 4194560 500 0 0 0 300 45 0 0 20 0 30 0 999 100 200 18446744073709551615";
+        assert_eq!(parse_stat(stat), Some(("Web Content (1)".to_string(), 345)));
+        let stat = "7 (a) b) c) S 1 7 7 0 -1 0 0 0 0 0 1 2 0 0 20 0 1 0 1 1 1";
+        assert_eq!(parse_stat(stat), Some(("a) b) c".to_string(), 3)));
+    }
+
+    #[test]
+    fn stat_truncated() {
+        assert_eq!(parse_stat("99 (gone) Z 1 99"), None);
+        assert_eq!(parse_stat(""), None);
+    }
+
+    #[test]
+    fn signals() {
+        for signal in SIGNALS {
+            assert_ne!(signal_name(signal), "signal");
+        }
+        // no such process
+        let e = send_signal(i32::MAX, libc::SIGCONT).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ESRCH));
+    }
+
+    #[test]
+    fn filter_is_case_insensitive() {
+        let mut list = ProcessList::new();
+        list.procs = vec![
+            ProcessInfo { pid: 4321, name: "Firefox".into(), user: "Alice".into(), cpu: 0.0, rss: 0 },
+            ProcessInfo { pid: 1, name: "systemd".into(), user: "root".into(), cpu: 0.0, rss: 0 },
+        ];
+        let pids = |filter: &str| list.filtered(filter).iter().map(|p| p.pid).collect::<Vec<_>>();
+        assert_eq!(pids("ALICE"), [4321]);
+        assert_eq!(pids("fireFOX"), [4321]);
+        assert_eq!(pids("Root"), [1]);
+        assert_eq!(pids("43"), [4321]);
+    }
+}
@@ -0,0 +1,310 @@
+//! Git project launcher: scans the configured roots for repositories
+
+use chrono::{DateTime, Local, TimeZone};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, Receiver};
+
+use crate::config::{expand_home, Config, ProjectsConfig};
+use crate::utils::{log_to_file, shell_quote};
+
+/// git commands run at the same time while scanning
+const GIT_JOBS: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct GitProject {
+    pub path: PathBuf,
+    pub name: String,
+    pub branch: String,
+    pub last_commit: Option<DateTime<Local>>,
+    /// None until the background `git status` is done
+    pub dirty: Option<bool>,
+    pub remote_url: Option<String>,
+}
+
+impl GitProject {
+    pub fn editor_command(&self, config: &Config) -> String {
+        let path = shell_quote(&self.path.to_string_lossy());
+        match &config.projects.editor {
+            Some(editor) => editor.replace("{path}", &path),
+            None => format!("cd {} && {}", path, config.in_terminal("${EDITOR:-vi} .")),
+        }
+    }
+
+    pub fn terminal_command(&self, config: &Config) -> String {
+        format!("cd {} && {}", shell_quote(&self.path.to_string_lossy()), config.terminal)
+    }
+
+    pub fn remote_command(&self) -> Option<String> {
+        let url = web_url(self.remote_url.as_ref()?)?;
+        Some(format!("xdg-open {}", shell_quote(&url)))
+    }
+}
+
+pub struct Projects {
+    pub list: Vec<GitProject>,
+    /// False until the background scan sends the list
+    pub scanned: bool,
+    rx: Receiver<ScanEvent>,
+}
+
+enum ScanEvent {
+    List(Vec<GitProject>),
+    /// Index in the list, dirty
+    Status(usize, bool),
+}
+
+impl Projects {
+    /// Scans the roots in background: walking them and running git on every repository
+    /// can take a while, the UI keeps running meanwhile
+    pub fn load(config: &ProjectsConfig) -> Self {
+        let roots: Vec<PathBuf> = config.roots.iter().map(|r| expand_home(r)).collect();
+        let depth = config.depth;
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            let mut list = vec![];
+            for root in &roots {
+                find_repositories(root, depth, &mut list);
+            }
+            read_last_commits(&mut list);
+            list.sort_by_key(|p| std::cmp::Reverse(p.last_commit));
+            let paths: Vec<PathBuf> = list.iter().map(|p| p.path.clone()).collect();
+            if tx.send(ScanEvent::List(list)).is_err() {
+                return;
+            }
+
+            // git status is the slowest part on big repositories: the list is shown before
+            for (i, path) in paths.iter().enumerate() {
+                let output = Command::new("git")
+                    .arg("-C").arg(path)
+                    .args(["status", "--porcelain"])
+                    .stdin(Stdio::null())
+                    .stderr(Stdio::null())
+                    .output();
+                if let Ok(output) = output
+                    && output.status.success()
+                    && tx.send(ScanEvent::Status(i, !output.stdout.is_empty())).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { list: vec![], scanned: false, rx }
+    }
+
+    pub fn poll_status(&mut self) {
+        while let Ok(event) = self.rx.try_recv() {
+            match event {
+                ScanEvent::List(list) => {
+                    self.list = list;
+                    self.scanned = true;
+                },
+                ScanEvent::Status(i, dirty) => {
+                    if let Some(p) = self.list.get_mut(i) {
+                        p.dirty = Some(dirty);
+                    }
+                },
+            }
+        }
+    }
+
+    pub fn filtered(&self, filter: &str) -> Vec<&GitProject> {
+        let filter = filter.to_lowercase();
+        self.list.iter()
+            .filter(|p| p.name.to_lowercase().contains(&filter) || p.branch.to_lowercase().contains(&filter))
+            .collect()
+    }
+}
+
+fn find_repositories(dir: &Path, depth: usize, found: &mut Vec<GitProject>) {
+    let git_dir = dir.join(".git");
+    if git_dir.exists() {
+        if let Some(project) = read_project(dir, &git_dir) {
+            found.push(project);
+        }
+        return;
+    }
+    if depth == 0 {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if !hidden && entry.file_type().is_ok_and(|t| t.is_dir()) {
+            find_repositories(&entry.path(), depth - 1, found);
+        }
+    }
+}
+
+fn read_project(dir: &Path, git_dir: &Path) -> Option<GitProject> {
+    // worktrees and submodules have a .git file pointing to the real git dir
+    let git_dir = if git_dir.is_file() {
+        let contents = fs::read_to_string(git_dir).ok()?;
+        let target = PathBuf::from(contents.strip_prefix("gitdir:")?.trim());
+        if target.is_relative() { dir.join(target) } else { target }
+    } else {
+        git_dir.to_path_buf()
+    };
+
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let branch = match head.trim().strip_prefix("ref: ") {
+        Some(r) => r.trim_start_matches("refs/heads/").to_string(),
+        None => format!("detached {}", head.trim().chars().take(7).collect::<String>()),
+    };
+
+    Some(GitProject {
+        path: dir.to_path_buf(),
+        name: dir.file_name()?.to_string_lossy().to_string(),
+        branch,
+        last_commit: None,
+        dirty: None,
+        remote_url: read_remote_url(&git_dir),
+    })
+}
+
+/// Committer time of HEAD. The reflog would count checkouts and rebases too, and reading the
+/// commit ourselves means unpacking objects: git does it, for all the projects at once
+/// `git log` for each project, GIT_JOBS at a time: there can be hundreds of them
+fn read_last_commits(projects: &mut [GitProject]) {
+    for chunk in projects.chunks_mut(GIT_JOBS) {
+        let children: Vec<_> = chunk.iter()
+            .map(|p| Command::new("git")
+                .arg("-C").arg(&p.path)
+                .args(["log", "-1", "--format=%ct"])
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(|e| log_to_file(format!("Cannot run git log in {}: {}", p.path.display(), e)))
+                .ok())
+            .collect();
+        for (project, child) in chunk.iter_mut().zip(children) {
+            project.last_commit = child
+                .and_then(|c| c.wait_with_output().ok())
+                .filter(|o| o.status.success())
+                .and_then(|o| String::from_utf8_lossy(&o.stdout).trim().parse::<i64>().ok())
+                .and_then(|t| Local.timestamp_opt(t, 0).single());
+        }
+    }
+}
+
+/// Where the config and the refs are: linked worktrees point to the main git dir with `commondir`
+fn common_dir(git_dir: &Path) -> PathBuf {
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(common) => {
+            let common = PathBuf::from(common.trim());
+            if common.is_relative() { git_dir.join(common) } else { common }
+        },
+        Err(_) => git_dir.to_path_buf(),
+    }
+}
+
+fn read_remote_url(git_dir: &Path) -> Option<String> {
+    parse_remote_url(&fs::read_to_string(common_dir(git_dir).join("config")).ok()?)
+}
+
+/// URL of the "origin" remote, or of the first remote found
+fn parse_remote_url(config: &str) -> Option<String> {
+    let mut current_remote: Option<String> = None;
+    let mut first_url: Option<String> = None;
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            current_remote = line.strip_prefix("[remote \"")
+                .and_then(|l| l.strip_suffix("\"]"))
+                .map(|r| r.to_string());
+        } else if let (Some(remote), Some(url)) = (&current_remote, line.strip_prefix("url")) {
+            let url = url.trim_start().trim_start_matches('=').trim().to_string();
+            if remote == "origin" {
+                return Some(url);
+            }
+            first_url.get_or_insert(url);
+        }
+    }
+    first_url
+}
+
+/// git@github.com:owner/repo.git -> https://github.com/owner/repo
+pub fn web_url(remote: &str) -> Option<String> {
+    let url = if let Some(rest) = remote.strip_prefix("https://").or_else(|| remote.strip_prefix("http://")) {
+        format!("https://{}", rest)
+    } else if let Some(rest) = remote.strip_prefix("ssh://") {
+        let rest = rest.split_once('@').map(|(_, r)| r).unwrap_or(rest);
+        // drop the port, if any
+        let (host, path) = rest.split_once('/')?;
+        format!("https://{}/{}", host.split(':').next()?, path)
+    } else if let Some((user_host, path)) = remote.split_once(':') {
+        let host = user_host.split_once('@').map(|(_, h)| h).unwrap_or(user_host);
+        format!("https://{}/{}", host, path)
+    } else {
+        return None;
+    };
+    Some(url.trim_end_matches('/').trim_end_matches(".git").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn web_urls() {
+        assert_eq!(web_url("git@github.com:vncnz/fenrir.git").as_deref(), Some("https://github.com/vncnz/fenrir"));
+        assert_eq!(web_url("github.com:vncnz/fenrir").as_deref(), Some("https://github.com/vncnz/fenrir"));
+        assert_eq!(web_url("https://github.com/vncnz/fenrir.git").as_deref(), Some("https://github.com/vncnz/fenrir"));
+        assert_eq!(web_url("http://gitlab.example.org/group/sub/repo/").as_deref(), Some("https://gitlab.example.org/group/sub/repo"));
+        assert_eq!(web_url("ssh://git@codeberg.org:2222/owner/repo.git").as_deref(), Some("https://codeberg.org/owner/repo"));
+        assert_eq!(web_url("/srv/git/repo.git"), None);
+    }
+
+    #[test]
+    fn remote_from_config() {
+        let config = "[core]\n\trepositoryformatversion = 0\n\
+                      [remote \"upstream\"]\n\turl = https://github.com/other/fenrir.git\n\
+                      [remote \"origin\"]\n\turl = git@github.com:vncnz/fenrir.git\n\tfetch = +refs/heads/*:refs/remotes/origin/*\n\
+                      [branch \"main\"]\n\tremote = origin\n";
+        assert_eq!(parse_remote_url(config).as_deref(), Some("git@github.com:vncnz/fenrir.git"));
+        let config = "[remote \"upstream\"]\n\turl=https://github.com/other/fenrir.git\n";
+        assert_eq!(parse_remote_url(config).as_deref(), Some("https://github.com/other/fenrir.git"));
+        assert_eq!(parse_remote_url("[core]\n\tbare = false\n"), None);
+    }
+
+    #[test]
+    fn remote_of_a_worktree() {
+        let main = std::env::temp_dir().join(format!("fenrir-projects-{}", std::process::id()));
+        let worktree = main.join("worktrees/feature");
+        fs::create_dir_all(&worktree).unwrap();
+        fs::write(main.join("config"), "[remote \"origin\"]\n\turl = git@github.com:vncnz/fenrir.git\n").unwrap();
+        fs::write(worktree.join("commondir"), "../..\n").unwrap();
+        let url = read_remote_url(&worktree);
+        fs::remove_dir_all(&main).unwrap();
+        assert_eq!(url.as_deref(), Some("git@github.com:vncnz/fenrir.git"));
+    }
+
+    #[test]
+    fn untracked_files_make_it_dirty() {
+        let root = std::env::temp_dir().join(format!("fenrir-scan-{}", std::process::id()));
+        let repo = root.join("repo");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&repo).unwrap();
+        let init = Command::new("git").arg("-C").arg(&repo).args(["init", "-q"]).output();
+        if !init.is_ok_and(|o| o.status.success()) {
+            fs::remove_dir_all(&root).unwrap();
+            return;
+        }
+        fs::write(repo.join("new.txt"), "").unwrap();
+
+        let config = ProjectsConfig { roots: vec![root.to_string_lossy().to_string()], depth: 1, editor: None };
+        let mut projects = Projects::load(&config);
+        let started = std::time::Instant::now();
+        while projects.list.first().is_none_or(|p| p.dirty.is_none()) && started.elapsed().as_secs() < 10 {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            projects.poll_status();
+        }
+        fs::remove_dir_all(&root).unwrap();
+        assert!(projects.scanned);
+        assert_eq!(projects.list.len(), 1);
+        assert_eq!(projects.list[0].dirty, Some(true));
+    }
+}
@@ -0,0 +1,196 @@
+//! Recently used documents, read from ~/.local/share/recently-used.xbel
+
+use chrono::{DateTime, Local};
+use regex::Regex;
+use std::path::PathBuf;
+
+use crate::mime::local_path;
+use crate::utils::{data_home, shell_quote};
+
+#[derive(Debug, Clone)]
+pub struct RecentFile {
+    pub uri: String,
+    pub path: Option<PathBuf>,
+    pub name: String,
+    pub app_name: Option<String>,
+    pub app_exec: Option<String>,
+    pub time: Option<DateTime<Local>>,
+}
+
+impl RecentFile {
+    /// Command line for the application that recorded the file, or xdg-open
+    pub fn open_command(&self) -> String {
+        let quoted_uri = shell_quote(&self.uri);
+        let quoted_path = self.path.as_ref()
+            .map(|p| shell_quote(&p.to_string_lossy()))
+            .unwrap_or_else(|| quoted_uri.clone());
+        match &self.app_exec {
+            Some(exec) if exec.contains("%u") || exec.contains("%U") || exec.contains("%f") || exec.contains("%F") => {
+                exec.replace("%u", &quoted_uri)
+                    .replace("%U", &quoted_uri)
+                    .replace("%f", &quoted_path)
+                    .replace("%F", &quoted_path)
+            },
+            _ => format!("xdg-open {}", quoted_uri),
+        }
+    }
+
+    pub fn open_folder_command(&self) -> Option<String> {
+        let parent = self.path.as_ref()?.parent()?;
+        Some(format!("xdg-open {}", shell_quote(&parent.to_string_lossy())))
+    }
+
+    pub fn folder(&self) -> String {
+        self.path.as_ref()
+            .and_then(|p| p.parent())
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|| self.uri.clone())
+    }
+}
+
+pub fn xbel_path() -> Option<PathBuf> {
+    data_home().map(|d| d.join("recently-used.xbel"))
+}
+
+pub fn load_recent_files() -> Vec<RecentFile> {
+    let Some(contents) = xbel_path().and_then(|p| std::fs::read_to_string(p).ok()) else {
+        return vec![];
+    };
+    recent_files(&contents)
+}
+
+/// The bookmarks whose file still exists, most recent first
+fn recent_files(contents: &str) -> Vec<RecentFile> {
+    let mut files: Vec<RecentFile> = parse_xbel(contents).into_iter()
+        .filter(|f| f.path.as_ref().is_none_or(|p| p.exists()))
+        .collect();
+    files.sort_by_key(|f| std::cmp::Reverse(f.time));
+    files
+}
+
+pub fn parse_xbel(contents: &str) -> Vec<RecentFile> {
+    let bookmark_re = Regex::new(r"(?s)<bookmark\s([^>]*)>(.*?)</bookmark>").unwrap();
+    let app_re = Regex::new(r"<bookmark:application\s([^>]*?)/?>").unwrap();
+    let attr_re = Regex::new(r#"([\w:-]+)="([^"]*)""#).unwrap();
+
+    let attr = |attrs: &str, name: &str| -> Option<String> {
+        attr_re.captures_iter(attrs)
+            .find(|c| &c[1] == name)
+            .map(|c| unescape_xml(&c[2]))
+    };
+
+    let mut results = vec![];
+    for bookmark in bookmark_re.captures_iter(contents) {
+        let Some(uri) = attr(&bookmark[1], "href") else {
+            continue;
+        };
+        // remote URIs (sftp://, smb://...) have no local path
+        let path = uri.starts_with("file://").then(|| local_path(&uri)).flatten();
+        let name = path.as_ref()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| uri.clone());
+
+        // the most recent application wins
+        let mut app: Option<(String, String, Option<DateTime<Local>>)> = None;
+        for a in app_re.captures_iter(&bookmark[2]) {
+            let modified = attr(&a[1], "modified").and_then(|m| parse_time(&m));
+            if app.as_ref().is_none_or(|(_, _, t)| modified > *t) {
+                app = Some((
+                    attr(&a[1], "name").unwrap_or_default(),
+                    attr(&a[1], "exec").unwrap_or_default().trim_matches('\'').to_string(),
+                    modified,
+                ));
+            }
+        }
+
+        let time = ["visited", "modified", "added"].iter()
+            .filter_map(|k| attr(&bookmark[1], k).and_then(|t| parse_time(&t)))
+            .max();
+        let (app_name, app_exec) = match app {
+            Some((name, exec, _)) => (Some(name).filter(|n| !n.is_empty()), Some(exec).filter(|e| !e.is_empty())),
+            None => (None, None),
+        };
+
+        results.push(RecentFile { uri, path, name, app_name, app_exec, time });
+    }
+    results
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(value).ok().map(|t| t.with_timezone(&Local))
+}
+
+fn unescape_xml(value: &str) -> String {
+    value.replace("&apos;", "'")
+        .replace("&quot;", "\"")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xbel(dir: &str) -> String {
+        format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<xbel version="1.0" xmlns:bookmark="http://www.freedesktop.org/standards/desktop-bookmarks">
+  <bookmark href="file://{dir}/old%20notes.txt" added="2024-01-01T10:00:00Z" modified="2024-01-01T10:00:00Z" visited="2024-01-01T10:00:00Z">
+    <info><metadata owner="http://freedesktop.org"><mime:mime-type type="text/plain"/>
+      <bookmark:applications>
+        <bookmark:application name="gedit" exec="&apos;gedit %u&apos;" modified="2024-01-01T10:00:00Z" count="1"/>
+        <bookmark:application name="Text &amp; Code" exec="&apos;code --goto %f&apos;" modified="2024-03-01T10:00:00Z" count="2"/>
+      </bookmark:applications>
+    </metadata></info>
+  </bookmark>
+  <bookmark href="file://localhost{dir}/new.pdf" added="2024-02-01T10:00:00Z" modified="2024-05-01T10:00:00Z" visited="2024-04-01T10:00:00Z">
+  </bookmark>
+  <bookmark href="file://{dir}/deleted.txt" added="2024-06-01T10:00:00Z" modified="2024-06-01T10:00:00Z" visited="2024-06-01T10:00:00Z">
+  </bookmark>
+  <bookmark href="sftp://host/remote.txt" added="2024-01-15T10:00:00Z" modified="2024-01-15T10:00:00Z" visited="2024-01-15T10:00:00Z">
+  </bookmark>
+</xbel>"#)
+    }
+
+    #[test]
+    fn parse_bookmarks() {
+        let files = parse_xbel(&xbel("/home/me/docs"));
+        assert_eq!(files.len(), 4);
+
+        let notes = &files[0];
+        assert_eq!(notes.path, Some(PathBuf::from("/home/me/docs/old notes.txt")));
+        assert_eq!(notes.name, "old notes.txt");
+        assert_eq!(notes.folder(), "/home/me/docs");
+        // the application that used the file last, entities decoded
+        assert_eq!(notes.app_name.as_deref(), Some("Text & Code"));
+        assert_eq!(notes.app_exec.as_deref(), Some("code --goto %f"));
+        assert_eq!(notes.open_command(), "code --goto '/home/me/docs/old notes.txt'");
+
+        // file://localhost/ is a local file too, the latest of visited/modified/added wins
+        let pdf = &files[1];
+        assert_eq!(pdf.path, Some(PathBuf::from("/home/me/docs/new.pdf")));
+        assert_eq!(pdf.time, parse_time("2024-05-01T10:00:00Z"));
+        assert_eq!(pdf.app_exec, None);
+        assert_eq!(pdf.open_command(), "xdg-open 'file://localhost/home/me/docs/new.pdf'");
+
+        let remote = &files[3];
+        assert_eq!(remote.path, None);
+        assert_eq!(remote.name, "sftp://host/remote.txt");
+        assert_eq!(remote.open_folder_command(), None);
+    }
+
+    #[test]
+    fn missing_files_dropped_and_sorted() {
+        let dir = std::env::temp_dir().join(format!("fenrir-recent-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("old notes.txt"), "").unwrap();
+        std::fs::write(dir.join("new.pdf"), "").unwrap();
+
+        let files = recent_files(&xbel(&dir.to_string_lossy()));
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        // deleted.txt is gone, the remote file can't be checked and stays
+        assert_eq!(names, ["new.pdf", "sftp://host/remote.txt", "old notes.txt"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,247 @@
+//! tmux and zellij sessions: list, attach and create
+
+use regex::Regex;
+use std::process::{Command, Stdio};
+
+use crate::config::Config;
+use crate::utils::shell_quote;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Multiplexer {
+    Tmux,
+    Zellij,
+}
+
+impl Multiplexer {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Multiplexer::Tmux => "tmux",
+            Multiplexer::Zellij => "zellij",
+        }
+    }
+
+    /// The name a new session gets: tmux doesn't accept '.' and ':'
+    pub fn session_name(&self, name: &str) -> String {
+        match self {
+            Multiplexer::Tmux => name.replace(['.', ':'], "_"),
+            Multiplexer::Zellij => name.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub name: String,
+    pub multiplexer: Multiplexer,
+    /// zellij doesn't report how many tabs a session has
+    pub windows: Option<usize>,
+    pub attached: bool,
+    /// zellij keeps exited sessions around to resurrect them
+    pub exited: bool,
+    pub created: Option<String>,
+}
+
+impl Session {
+    pub fn attach_command(&self, config: &Config) -> String {
+        let name = shell_quote(&self.name);
+        match self.multiplexer {
+            Multiplexer::Tmux => config.in_terminal(&format!("tmux attach-session -t {}", name)),
+            Multiplexer::Zellij => config.in_terminal(&format!("zellij attach {}", name)),
+        }
+    }
+}
+
+pub enum SessionRow<'a> {
+    Existing(&'a Session),
+    Create(Multiplexer, String),
+}
+
+pub fn create_command(multiplexer: Multiplexer, name: &str, config: &Config) -> String {
+    let name = shell_quote(&multiplexer.session_name(name));
+    match multiplexer {
+        Multiplexer::Tmux => config.in_terminal(&format!("tmux new-session -s {}", name)),
+        Multiplexer::Zellij => config.in_terminal(&format!("zellij attach --create {}", name)),
+    }
+}
+
+pub struct Sessions {
+    pub list: Vec<Session>,
+    /// Multiplexers installed on this machine, even without running sessions
+    pub available: Vec<Multiplexer>,
+}
+
+impl Sessions {
+    pub fn load() -> Self {
+        let mut list = vec![];
+        let mut available = vec![];
+
+        let tmux = Command::new("tmux")
+            .args(["list-sessions", "-F", "#{session_name}\t#{session_windows}\t#{session_attached}"])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output();
+        // "no server running" exits with an error, but tmux is there
+        if let Ok(output) = tmux {
+            available.push(Multiplexer::Tmux);
+            list.extend(parse_tmux_sessions(&String::from_utf8_lossy(&output.stdout)));
+        }
+
+        let zellij = Command::new("zellij")
+            .args(["list-sessions", "--no-formatting"])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output();
+        if let Ok(output) = zellij {
+            available.push(Multiplexer::Zellij);
+            list.extend(parse_zellij_sessions(&String::from_utf8_lossy(&output.stdout)));
+        }
+
+        Self { list, available }
+    }
+
+    /// Matching sessions, followed by "create" rows when no session has exactly that name
+    pub fn rows(&self, filter: &str) -> Vec<SessionRow<'_>> {
+        let name = filter.trim();
+        let mut rows: Vec<SessionRow> = self.list.iter()
+            .filter(|s| s.name.to_lowercase().contains(&name.to_lowercase()))
+            .map(SessionRow::Existing)
+            .collect();
+        if !name.is_empty() {
+            for m in &self.available {
+                let new_name = m.session_name(name);
+                if !self.list.iter().any(|s| s.multiplexer == *m && s.name == new_name) {
+                    rows.push(SessionRow::Create(*m, new_name));
+                }
+            }
+        }
+        rows
+    }
+}
+
+/// Parses `tmux list-sessions -F "#{session_name}\t#{session_windows}\t#{session_attached}"`
+pub fn parse_tmux_sessions(output: &str) -> Vec<Session> {
+    output.lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let name = fields.next().filter(|n| !n.is_empty())?;
+            let windows = fields.next().and_then(|w| w.trim().parse().ok());
+            // number of attached clients
+            let attached = fields.next().and_then(|a| a.trim().parse::<u32>().ok()).unwrap_or(0) > 0;
+            Some(Session {
+                name: name.to_string(),
+                multiplexer: Multiplexer::Tmux,
+                windows,
+                attached,
+                exited: false,
+                created: None,
+            })
+        })
+        .collect()
+}
+
+/// Parses `zellij list-sessions --no-formatting`, like
+/// `work [Created 2h 3m ago] (current)` or `old [Created 5days ago] (EXITED - attach to resurrect)`.
+/// Older versions print only the names
+pub fn parse_zellij_sessions(output: &str) -> Vec<Session> {
+    // colours are printed anyway by some versions
+    let ansi = Regex::new(r"\x1b\[[0-9;]*m").unwrap();
+    let created_re = Regex::new(r"\[Created (.*?) ago\]").unwrap();
+    output.lines()
+        .filter_map(|line| {
+            let line = ansi.replace_all(line, "");
+            let name = line.split_whitespace().next()?;
+            if name.starts_with("No") && line.contains("active zellij sessions") {
+                return None;
+            }
+            Some(Session {
+                name: name.to_string(),
+                multiplexer: Multiplexer::Zellij,
+                windows: None,
+                attached: line.contains("(current)"),
+                exited: line.contains("EXITED"),
+                created: created_re.captures(&line).map(|c| c[1].to_string()),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tmux_sessions() {
+        let output = "main\t3\t1\nscratch\t1\t0\nmy session\t12\t2\n";
+        let sessions = parse_tmux_sessions(output);
+        assert_eq!(sessions.len(), 3);
+        assert_eq!(sessions[0].name, "main");
+        assert_eq!(sessions[0].windows, Some(3));
+        assert!(sessions[0].attached);
+        assert!(!sessions[1].attached);
+        assert_eq!(sessions[2].name, "my session");
+        assert_eq!(sessions[2].windows, Some(12));
+        assert!(sessions[2].attached);
+    }
+
+    #[test]
+    fn create_rows_use_the_sanitised_name() {
+        let sessions = Sessions {
+            list: parse_tmux_sessions("a_b\t1\t0\n"),
+            available: vec![Multiplexer::Tmux, Multiplexer::Zellij],
+        };
+        let created: Vec<(Multiplexer, String)> = sessions.rows("a.b").into_iter()
+            .filter_map(|r| match r {
+                SessionRow::Create(m, name) => Some((m, name)),
+                SessionRow::Existing(_) => None,
+            })
+            .collect();
+        assert_eq!(created, vec![(Multiplexer::Zellij, "a.b".to_string())]);
+        assert!(matches!(sessions.rows("a:c").last(), Some(SessionRow::Create(Multiplexer::Zellij, _))));
+        assert!(sessions.rows("a:c").iter().any(|r| matches!(r, SessionRow::Create(Multiplexer::Tmux, name) if name == "a_c")));
+    }
+
+    #[test]
+    fn tmux_no_server() {
+        assert!(parse_tmux_sessions("").is_empty());
+    }
+
+    #[test]
+    fn zellij_sessions() {
+        let output = "fenrir [Created 2h 3m 10s ago] (current)\n\
+                      notes [Created 1day ago] \n\
+                      old-one [Created 5days ago] (EXITED - attach to resurrect)\n";
+        let sessions = parse_zellij_sessions(output);
+        assert_eq!(sessions.len(), 3);
+        assert_eq!(sessions[0].name, "fenrir");
+        assert!(sessions[0].attached);
+        assert_eq!(sessions[0].created.as_deref(), Some("2h 3m 10s"));
+        assert!(!sessions[1].attached);
+        assert!(!sessions[1].exited);
+        assert!(sessions[2].exited);
+        assert_eq!(sessions[2].windows, None);
+    }
+
+    #[test]
+    fn zellij_colored_and_old_output() {
+        let output = "\x1b[32;1mwork\x1b[m [Created \x1b[35;1m10m\x1b[m ago]\nplain\n";
+        let sessions = parse_zellij_sessions(output);
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].name, "work");
+        assert_eq!(sessions[0].created.as_deref(), Some("10m"));
+        assert_eq!(sessions[1].name, "plain");
+        assert!(parse_zellij_sessions("No active zellij sessions found.\n").is_empty());
+    }
+
+    #[test]
+    fn create_rows() {
+        let sessions = Sessions {
+            list: parse_tmux_sessions("main\t3\t1\n"),
+            available: vec![Multiplexer::Tmux, Multiplexer::Zellij],
+        };
+        let rows = sessions.rows("main");
+        assert_eq!(rows.len(), 2);
+        assert!(matches!(rows[0], SessionRow::Existing(s) if s.name == "main"));
+        assert!(matches!(&rows[1], SessionRow::Create(Multiplexer::Zellij, n) if n == "main"));
+        assert!(sessions.rows("").iter().all(|r| matches!(r, SessionRow::Existing(_))));
+    }
+}
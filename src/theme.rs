@@ -0,0 +1,66 @@
+//! The styles of the UI, from the [theme] section of the config
+
+use ratatui::style::{Color, Modifier, Style};
+use std::str::FromStr;
+
+use crate::config::{StyleConfig, ThemeConfig};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub highlight: Style,
+    pub text: Style,
+    pub label: Style,
+    pub exec: Style,
+    pub comment: Style,
+    pub hint: Style,
+    pub dim: Style,
+    pub good: Style,
+    pub error: Style,
+    pub border: Style,
+}
+
+/// Invalid colours are reported in `errors` and left out
+fn resolve(name: &str, config: &StyleConfig, errors: &mut Vec<String>) -> Style {
+    let mut color = |value: &Option<String>| match value.as_deref().map(Color::from_str) {
+        Some(Ok(color)) => Some(color),
+        Some(Err(_)) => {
+            errors.push(format!("theme.{}: unknown colour '{}'", name, value.as_deref().unwrap_or_default()));
+            None
+        },
+        None => None,
+    };
+    let mut style = Style::default();
+    if let Some(fg) = color(&config.fg) {
+        style = style.fg(fg);
+    }
+    if let Some(bg) = color(&config.bg) {
+        style = style.bg(bg);
+    }
+    if config.bold {
+        style = style.add_modifier(Modifier::BOLD);
+    }
+    if config.italic {
+        style = style.add_modifier(Modifier::ITALIC);
+    }
+    if config.underlined {
+        style = style.add_modifier(Modifier::UNDERLINED);
+    }
+    style
+}
+
+impl Theme {
+    pub fn new(config: &ThemeConfig, errors: &mut Vec<String>) -> Self {
+        Self {
+            highlight: resolve("highlight", &config.highlight, errors),
+            text: resolve("text", &config.text, errors),
+            label: resolve("label", &config.label, errors),
+            exec: resolve("exec", &config.exec, errors),
+            comment: resolve("comment", &config.comment, errors),
+            hint: resolve("hint", &config.hint, errors),
+            dim: resolve("dim", &config.dim, errors),
+            good: resolve("good", &config.good, errors),
+            error: resolve("error", &config.error, errors),
+            border: resolve("border", &config.border, errors),
+        }
+    }
+}
@@ -1,53 +1,447 @@
 use crate::app::{load_app_entries, AppEntry};
+use crate::daemon::{DaemonClient, DaemonEvent};
 use crate::data::{RatatoskrSocket, PartialMsg};
+use crate::groups::LaunchGroups;
+use crate::history::{state_dir, LaunchHistory};
+use crate::icons::{is_placeholder, Icons, Protocol};
+use crate::presets::ArgPresets;
 // use crate::data_sources::read_ratatoskr;
-use crate::utils::{get_color_gradient, log_to_file};
+use crate::projects::{GitProject, Projects};
+use crate::recent::{load_recent_files, RecentFile};
+use crate::sessions::{create_command, SessionRow, Sessions};
+use crate::processes::{format_bytes, send_signal, signal_name, ProcessInfo, ProcessList, SortKey, SIGNALS};
+use crate::config::{ColumnsConfig, Config, ProfileConfig};
+use crate::mime::{self, MimeApps};
+use crate::keymap::{Action, Keymap};
+use crate::lineedit::{LineEditor, QueryHistory};
+use crate::launcher::{Launch, LaunchFailure, LaunchRequest, LAUNCH_LOG};
+use crate::utils::{get_color_gradient, get_color_gradient_full, log_to_file, relative_time, save_json, shell_quote};
+use crate::theme::Theme;
+use crate::websearch::{WebSearch, WebTarget};
 
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Alignment, Constraint, Direction, Layout, Margin, Position, Rect},
     text::{Line, Span},
-    style::{Style, Color},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState, Wrap},
     Terminal,
 };
-use crossterm::event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode};
+use crossterm::event::{self, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEventKind};
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
 use std::{io, time::Instant};
-use std::process::{Command, Stdio};
-use std::fs::OpenOptions;
-use regex::Regex;
 use std::collections::HashMap;
+use serde_derive::{Deserialize, Serialize};
 
 // use chrono::Local;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Apps,
+    Processes,
+    Recent,
+    Projects,
+    Sessions,
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "apps" => Some(Mode::Apps),
+            "processes" | "ps" => Some(Mode::Processes),
+            "recent" => Some(Mode::Recent),
+            "projects" | "git" => Some(Mode::Projects),
+            "sessions" | "tmux" | "zellij" => Some(Mode::Sessions),
+            _ => None,
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Mode::Apps => "Applications",
+            Mode::Processes => "Processes",
+            Mode::Recent => "Recent files",
+            Mode::Projects => "Projects",
+            Mode::Sessions => "Sessions",
+        }
+    }
+
+    /// The following mode, in the F1-F5 order
+    fn next(&self) -> Mode {
+        match self {
+            Mode::Apps => Mode::Processes,
+            Mode::Processes => Mode::Recent,
+            Mode::Recent => Mode::Projects,
+            Mode::Projects => Mode::Sessions,
+            Mode::Sessions => Mode::Apps,
+        }
+    }
+}
+
+enum AppRow<'a> {
+    App(&'a AppEntry),
+    Web(WebTarget),
+    /// Saved launch group: name and desktop IDs
+    Group(&'a str, &'a [String]),
+    /// Launch profile from the config
+    Profile(&'a str, &'a ProfileConfig),
+}
 
-pub fn launch_detached(app: &AppEntry) {
-    // let exec = &app.exec;
-    let re = Regex::new(r"%[UufFdDnNickvm]").unwrap();
-    let exec = re.replace_all(&app.exec, "").to_string();
-
-    // Log file in caso di errori
-    let log_file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("/tmp/fenrir-launcher.log")
-        .unwrap_or_else(|_| std::fs::File::create("/dev/null").unwrap());
-
-    let result = Command::new("setsid")
-        .arg("sh")
-        .arg("-c")
-        .arg(&exec)
-        .stdin(Stdio::null())
-        .stdout(Stdio::from(log_file.try_clone().unwrap()))
-        .stderr(Stdio::from(log_file))
-        .spawn();
-
-    if let Err(e) = result {
-        eprintln!("Failed to launch '{}': {}", exec, e);
+/// `marker` is empty when nothing is marked, so the list doesn't move.
+/// `icon` is the 2 cells wide icon, when icons are shown
+fn app_line(a: &AppEntry, marker: &'static str, icon: Option<Vec<Span<'static>>>, theme: &Theme, columns: &ColumnsConfig) -> Line<'static> {
+    // format!("{} {} - {} - {}", if a.terminal { "" } else { "" }, a.name, a.exec, a.comment)
+    let mut spans = vec![Span::styled(marker, theme.good)];
+    if let Some(icon) = icon {
+        spans.extend(icon);
+        spans.push(Span::raw(" "));
+    }
+    spans.extend([
+        Span::styled(if a.terminal { "" } else { "" }, theme.label),
+        Span::styled(format!(" {}", a.name), theme.text),
+    ]);
+    if columns.exec {
+        spans.push(Span::styled(format!(" {}", a.exec), theme.exec));
     }
+    if columns.comment {
+        spans.push(Span::styled(format!(" {}", a.comment), theme.comment));
+    }
+    Line::from(spans)
 }
 
+/// The highlight fg on the text of the row, not on the kitty icon: its colour is the image ID
+fn highlight_text(mut line: Line<'static>, highlight: Style) -> Line<'static> {
+    if let Some(fg) = highlight.fg {
+        for span in line.spans.iter_mut().filter(|span| !is_placeholder(span)) {
+            span.style = span.style.fg(fg);
+        }
+    }
+    line
+}
+
+fn web_line(w: &WebTarget, query: &str, theme: &Theme) -> Line<'static> {
+    Line::from(vec![
+        Span::styled("\u{f059f}", theme.label),
+        Span::styled(format!(" {}: {}", w.label, query.trim()), theme.text),
+        Span::styled(format!(" {}", w.url), theme.exec),
+    ])
+}
+
+fn group_line(name: &str, app_ids: &[String], apps: &[AppEntry], theme: &Theme) -> Line<'static> {
+    let names: Vec<&str> = app_ids.iter()
+        .map(|id| apps.iter().find(|a| &a.id == id).map(|a| a.name.as_str()).unwrap_or(id))
+        .collect();
+    Line::from(vec![
+        Span::styled("\u{f0c0}", theme.label),
+        Span::styled(format!(" {}", name), theme.text),
+        Span::styled(format!(" {}", names.join(", ")), theme.exec),
+    ])
+}
+
+fn profile_line(name: &str, profile: &ProfileConfig, theme: &Theme) -> Line<'static> {
+    let apps: Vec<String> = profile.apps.iter()
+        .map(|p| {
+            let app = p.app.as_deref().or(p.command.as_deref()).unwrap_or("?");
+            match p.workspace.as_ref().or(profile.workspace.as_ref()) {
+                Some(workspace) => format!("{}@{}", app, workspace),
+                None => app.to_string(),
+            }
+        })
+        .collect();
+    Line::from(vec![
+        Span::styled("\u{f0ce}", theme.label),
+        Span::styled(format!(" {}", name), theme.text),
+        Span::styled(format!(" {}", apps.join(", ")), theme.exec),
+    ])
+}
+
+/// The launches of a profile, apps that aren't installed are skipped
+fn profile_requests(name: &str, profile: &ProfileConfig, apps: &[AppEntry]) -> Vec<LaunchRequest> {
+    profile.apps.iter()
+        .filter_map(|p| {
+            let request = match (&p.app, &p.command) {
+                (Some(id), _) => {
+                    let app = apps.iter()
+                        .find(|a| a.id == *id || a.id == format!("{}.desktop", id) || a.name.eq_ignore_ascii_case(id));
+                    if app.is_none() {
+                        log_to_file(format!("Profile '{}': no app '{}'", name, id));
+                    }
+                    LaunchRequest::for_app(app?)
+                },
+                (None, Some(command)) => LaunchRequest::command(command),
+                (None, None) => return None,
+            };
+            Some(LaunchRequest { workspace: p.workspace.clone().or_else(|| profile.workspace.clone()), ..request })
+        })
+        .collect()
+}
+
+/// Command line of an app being edited before launching it
+struct ArgsEditor {
+    app: AppEntry,
+    /// The expanded Exec, Down past the presets goes back to it
+    initial: String,
+    input: LineEditor,
+    /// Position in the app presets while browsing them with Up/Down
+    preset: Option<usize>,
+}
+
+/// Error panel for a failed launch, `editing` shows the command being edited
+fn failure_panel(f: &mut ratatui::Frame, failure: &LaunchFailure, editing: Option<&str>, theme: &Theme) {
+    let area = f.area();
+    let height = (failure.output.len() as u16 + 7).min(area.height);
+    let width = area.width.saturating_sub(4).min(120);
+    let rect = Rect::new((area.width - width) / 2, (area.height - height) / 2, width, height);
+
+    let mut lines = vec![
+        Line::from(vec![
+            Span::styled("Command: ", theme.label),
+            Span::styled(editing.unwrap_or(&failure.request.command).to_string(), theme.exec),
+        ]),
+        Line::from(Span::styled(failure.error.clone(), theme.error)),
+        Line::from(""),
+    ];
+    lines.extend(failure.output.iter().map(|l| Line::from(Span::styled(l.clone(), theme.comment))));
+    let hints = if editing.is_some() {
+        "Enter: launch edited command  Esc: stop editing"
+    } else {
+        "r: retry  e: edit command  l: open log  Esc: close"
+    };
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(hints, theme.label)));
+
+    f.render_widget(Clear, rect);
+    f.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(Block::default().borders(Borders::ALL).border_style(theme.error).title("Launch failed")),
+        rect,
+    );
+}
+
+/// Properties of the selected row, `icon_rows` are left empty at the top for the icon
+fn details_lines(row: &AppRow, history: &LaunchHistory, theme: &Theme, icon_rows: u16) -> Vec<Line<'static>> {
+    let mut lines: Vec<Line> = (0..icon_rows).map(|_| Line::from("")).collect();
+    let field = |label: &str, value: String, style: Style| Line::from(vec![
+        Span::styled(format!("{}: ", label), theme.label),
+        Span::styled(value, style),
+    ]);
+    match row {
+        AppRow::App(app) => {
+            lines.push(Line::styled(app.name.clone(), theme.text.add_modifier(Modifier::BOLD)));
+            if let Some(generic) = &app.generic_name {
+                lines.push(Line::styled(generic.clone(), theme.comment));
+            }
+            if !app.comment.is_empty() {
+                lines.push(Line::styled(app.comment.clone(), theme.comment));
+            }
+            lines.push(Line::from(""));
+            if !app.categories.is_empty() {
+                lines.push(field("Categories", app.categories.join(", "), theme.text));
+            }
+            if !app.keywords.is_empty() {
+                lines.push(field("Keywords", app.keywords.join(", "), theme.text));
+            }
+            lines.push(field("Exec", app.exec.clone(), theme.exec));
+            let expanded = app.expand_exec("");
+            if expanded != app.exec {
+                lines.push(field("Runs", expanded, theme.exec));
+            }
+            if app.terminal {
+                lines.push(field("Terminal", "yes".into(), theme.text));
+            }
+            lines.push(field("File", app.desktop_file.to_string_lossy().to_string(), theme.dim));
+            if let Some(dir) = app.desktop_file.parent() {
+                lines.push(field("Source", dir.to_string_lossy().to_string(), theme.dim));
+            }
+            if !app.mime_types.is_empty() {
+                lines.push(field("MIME types", app.mime_types.join(", "), theme.dim));
+            }
+            if !app.actions.is_empty() {
+                lines.push(field("Actions", app.actions.join(", "), theme.text));
+            }
+            if let Some(entry) = history.apps.get(&app.id) {
+                let last = chrono::DateTime::from_timestamp(entry.last, 0)
+                    .map(|t| relative_time(t.with_timezone(&chrono::Local)))
+                    .unwrap_or_default();
+                lines.push(Line::from(""));
+                lines.push(field("Launched", format!("{} times, last {}", entry.count, last), theme.good));
+            }
+        },
+        AppRow::Web(w) => {
+            lines.push(Line::styled(w.label.clone(), theme.text.add_modifier(Modifier::BOLD)));
+            lines.push(field("URL", w.url.clone(), theme.exec));
+        },
+        AppRow::Group(name, app_ids) => {
+            lines.push(Line::styled(format!("Group {}", name), theme.text.add_modifier(Modifier::BOLD)));
+            lines.extend(app_ids.iter().map(|id| Line::styled(format!("  {}", id), theme.text)));
+        },
+        AppRow::Profile(name, profile) => {
+            lines.push(Line::styled(format!("Profile {}", name), theme.text.add_modifier(Modifier::BOLD)));
+            for app in &profile.apps {
+                let what = app.app.clone().or_else(|| app.command.clone()).unwrap_or_default();
+                let workspace = app.workspace.as_ref().or(profile.workspace.as_ref())
+                    .map(|w| format!(" (workspace {})", w))
+                    .unwrap_or_default();
+                lines.push(Line::styled(format!("  {}{}", what, workspace), theme.text));
+            }
+        },
+    }
+    lines
+}
+
+/// Size of the tiles of the grid view, in cells
+const TILE_WIDTH: u16 = 16;
+const TILE_HEIGHT: u16 = 6;
+
+/// Where the icon goes in a tile: 4 rows, square with 1:2 cells, above the name
+fn tile_icon_area(tile: Rect) -> Rect {
+    let width = 8.min(tile.width);
+    Rect::new(tile.x + (tile.width - width) / 2, tile.y + 1, width, 4.min(tile.height.saturating_sub(2)))
+}
+
+/// A tile of the grid view: the name at the bottom, the kind of row in place of a missing icon
+fn grid_tile(f: &mut ratatui::Frame, tile: Rect, row: &AppRow, selected: bool, no_icon: bool, theme: &Theme) {
+    f.render_widget(Block::default().style(if selected { theme.highlight } else { Style::default() }), tile);
+    let (name, kind) = match row {
+        AppRow::App(a) => (a.name.as_str(), if a.terminal { "term" } else { "app" }),
+        AppRow::Web(w) => (w.label.as_str(), "web"),
+        AppRow::Group(name, _) => (*name, "group"),
+        AppRow::Profile(name, _) => (*name, "profile"),
+    };
+    let width = tile.width.saturating_sub(2) as usize;
+    let name: String = name.chars().take(width).collect();
+    if tile.height >= TILE_HEIGHT {
+        let name_area = Rect::new(tile.x + 1, tile.y + TILE_HEIGHT - 1, width as u16, 1);
+        f.render_widget(Paragraph::new(Span::styled(name, theme.text)).alignment(Alignment::Center), name_area);
+    }
+    if no_icon {
+        let icon = tile_icon_area(tile);
+        let kind_area = Rect::new(tile.x, icon.y + icon.height / 2, tile.width, 1).intersection(tile);
+        f.render_widget(Paragraph::new(Span::styled(kind, theme.label)).alignment(Alignment::Center), kind_area);
+    }
+}
+
+/// Which view the apps are shown in, kept across runs
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ViewState {
+    grid: bool,
+}
+
+impl ViewState {
+    fn path() -> Option<std::path::PathBuf> {
+        state_dir().map(|d| d.join("view.json"))
+    }
+
+    fn load() -> Self {
+        Self::path()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        if let Some(path) = Self::path() {
+            save_json(&path, self, "the view");
+        }
+    }
+}
+
+/// Sends the signal, and tells how it went
+fn signal_notice(process: &ProcessInfo, signal: i32) -> String {
+    match send_signal(process.pid, signal) {
+        Ok(()) => format!("{} sent to {} ({})", signal_name(signal), process.name, process.pid),
+        Err(e) => {
+            log_to_file(format!("Cannot send {} to {}: {}", signal_name(signal), process.pid, e));
+            if e.kind() == io::ErrorKind::PermissionDenied {
+                format!("Not allowed to send {} to {} ({}), it belongs to {}", signal_name(signal), process.name, process.pid, process.user)
+            } else {
+                format!("{} to {} failed: {}", signal_name(signal), process.pid, e)
+            }
+        },
+    }
+}
+
+fn batch_progress(label: Option<&str>, next: &LaunchRequest, n: usize, total: usize) -> String {
+    let workspace = next.workspace.as_ref().map(|w| format!(" on workspace {}", w)).unwrap_or_default();
+    match label {
+        Some(label) => format!("{}: launching {}{} ({}/{})", label, next.label, workspace, n, total),
+        None => format!("Launching {}{} ({}/{})", next.label, workspace, n, total),
+    }
+}
+
+fn toggle_mark(marked: &mut Vec<String>, app_id: &str) {
+    match marked.iter().position(|id| id == app_id) {
+        Some(i) => { marked.remove(i); },
+        None => marked.push(app_id.to_string()),
+    }
+}
+
+fn recent_line(r: &RecentFile, theme: &Theme) -> Line<'static> {
+    Line::from(vec![
+        Span::styled(format!("{:>8} ", r.time.map(relative_time).unwrap_or_default()), theme.label),
+        Span::styled(r.name.clone(), theme.text),
+        Span::styled(format!(" {}", r.app_name.clone().unwrap_or_default()), theme.exec),
+        Span::styled(format!(" {}", r.folder()), theme.comment),
+    ])
+}
+
+fn project_line(p: &GitProject, theme: &Theme) -> Line<'static> {
+    let (dirty, dirty_style) = match p.dirty {
+        Some(true) => ("*", theme.error),
+        Some(false) => (" ", theme.label),
+        None => ("?", theme.dim),
+    };
+    Line::from(vec![
+        Span::styled(format!("{:>8} ", p.last_commit.map(relative_time).unwrap_or_default()), theme.label),
+        Span::styled(p.name.clone(), theme.text),
+        Span::styled(format!(" {}", p.branch), theme.exec),
+        Span::styled(dirty, dirty_style),
+        Span::styled(format!(" {}", p.path.display()), theme.comment),
+    ])
+}
+
+fn session_line(row: &SessionRow, theme: &Theme) -> Line<'static> {
+    match row {
+        SessionRow::Existing(s) => {
+            let (state, state_style) = if s.exited {
+                ("exited", theme.dim)
+            } else if s.attached {
+                ("attached", theme.good)
+            } else {
+                ("detached", theme.label)
+            };
+            let windows = s.windows.map(|w| format!("{} windows", w)).unwrap_or_default();
+            let created = s.created.as_ref().map(|c| format!("created {} ago", c)).unwrap_or_default();
+            Line::from(vec![
+                Span::styled(format!("{:<7}", s.multiplexer.name()), theme.label),
+                Span::styled(s.name.clone(), theme.text),
+                Span::styled(format!(" {}", state), state_style),
+                Span::styled(format!(" {}", windows), theme.exec),
+                Span::styled(format!(" {}", created), theme.comment),
+            ])
+        },
+        SessionRow::Create(m, name) => Line::from(vec![
+            Span::styled(format!("{:<7}", m.name()), theme.label),
+            Span::styled(format!("Create new session '{}'", name), theme.exec),
+        ]),
+    }
+}
+
+fn process_line(p: &crate::processes::ProcessInfo, mem_total: u64, theme: &Theme) -> Line<'static> {
+    let cpu_color = get_color_gradient_full(0.0, 100.0, p.cpu, false);
+    // 25% of the whole RAM is already a lot for a single process
+    let mem_color = get_color_gradient_full(0.0, mem_total as f64 / 4.0, p.rss as f64, false);
+    Line::from(vec![
+        Span::styled(format!("{:>7} ", p.pid), theme.label),
+        Span::styled(format!("{:<10.10} ", p.user), theme.comment),
+        Span::styled(format!("{:>6.1}% ", p.cpu), Style::default().fg(Color::Rgb(cpu_color.0, cpu_color.1, cpu_color.2))),
+        Span::styled(format!("{:>7} ", format_bytes(p.rss)), Style::default().fg(Color::Rgb(mem_color.0, mem_color.1, mem_color.2))),
+        Span::styled(p.name.clone(), theme.text),
+    ])
+}
+
+
 macro_rules! jstr {
     ($value:expr, $key:expr, $default:expr) => {
         $value.get($key)
@@ -56,7 +450,7 @@ macro_rules! jstr {
     };
 }
 
-pub fn update_span (paragraphs: &mut HashMap<String, Span>, data: PartialMsg) {
+pub fn update_span (paragraphs: &mut HashMap<String, Span>, data: PartialMsg, theme: &Theme) {
     // Extract the right paragraph or create a new one
     // Update it with updated data
     // return it
@@ -98,7 +492,7 @@ pub fn update_span (paragraphs: &mut HashMap<String, Span>, data: PartialMsg) {
                 if v > 0.0 {
                     span = Some(Span::styled(format!("[TEMP {:.0}°C] ", v), Style::default().fg(color)));
                 } else {
-                    span = Some(Span::styled("[NO TEMP] ", Style::default().fg(Color::Rgb(100, 100, 100))));
+                    span = Some(Span::styled("[NO TEMP] ", theme.dim));
                 }
             }
         },
@@ -108,7 +502,7 @@ pub fn update_span (paragraphs: &mut HashMap<String, Span>, data: PartialMsg) {
                 if v > 0.0 {
                     span = Some(Span::styled(format!("[VOL {:.0}%] ", v), Style::default().fg(color)));
                 } else {
-                    span = Some(Span::styled(format!("[MUTED] ", ), Style::default().fg(Color::Rgb(100, 100, 100))));
+                    span = Some(Span::styled("[MUTED] ", theme.dim));
                 }
             }
         },
@@ -127,7 +521,7 @@ pub fn update_span (paragraphs: &mut HashMap<String, Span>, data: PartialMsg) {
             }
         },
         "ratatoskr" => {
-            if data.warning == 1.0 { span = Some(Span::styled(format!("Ratatoskr disconnected"), Style::default().fg(color))); }
+            if data.warning == 1.0 { span = Some(Span::styled("Ratatoskr disconnected", Style::default().fg(color))); }
         },
         "display" => {},
         "weather" => {
@@ -152,23 +546,66 @@ pub fn update_span (paragraphs: &mut HashMap<String, Span>, data: PartialMsg) {
     }
 }
 
-pub fn run_ui(show_icons: bool, t0: Instant) -> io::Result<()> {
+pub fn run_ui(config: &Config, icon_protocol: Option<Protocol>, initial_mode: Mode, initial_query: &str, open_target: Option<&str>, t0: Instant) -> io::Result<()> {
     let mut t1: Option<Instant> = None;
     let mut t2: Option<Instant> = None;
     enable_raw_mode()?;
     let mut stdout = io::stdout();
-    crossterm::execute!(
-        stdout,
-        EnterAlternateScreen,
-        EnableMouseCapture
-    )?;
+    crossterm::execute!(stdout, EnterAlternateScreen, EnableBracketedPaste)?;
+    if config.mouse.capture {
+        crossterm::execute!(stdout, EnableMouseCapture)?;
+    }
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let mut filter = String::new();
+    let mut filter = LineEditor::new(initial_query);
+    let mut queries = QueryHistory::load();
     let mut selected = 0;
+    let mut mode = initial_mode;
+    let mut notice: Option<String> = None;
+    // launched in order, with launch.batch_delay_ms between them
+    let mut pending_launch: Vec<LaunchRequest> = vec![];
+    // what was still to launch when a batch failed, resumed by retry
+    let mut queued: Vec<LaunchRequest> = vec![];
+    // size of the running batch, 0 when there's none
+    let mut batch_total = 0;
+    // profile being launched, for the progress
+    let mut batch_label: Option<String> = None;
+    let mut launch_at = Instant::now();
+    // the launch being watched until the app is ready, the UI keeps running meanwhile
+    let mut launching: Option<Launch> = None;
+    // the log viewer doesn't close fenrir
+    let mut log_viewer: Option<Launch> = None;
+    let mut failure: Option<LaunchFailure> = None;
+    // Some while the command of the failed launch is being edited
+    let mut edited_command: Option<String> = None;
+    let mut args_editor: Option<ArgsEditor> = None;
+    let mut presets = ArgPresets::load();
+    // desktop IDs, in marking order
+    let mut marked: Vec<String> = vec![];
+    let mut groups = LaunchGroups::load();
+    // Some while the name of a new group is being typed
+    let mut group_name: Option<String> = None;
+    // process picked for a signal, and the index of the signal in SIGNALS
+    let mut signal_picker: Option<(ProcessInfo, usize)> = None;
+    // where the list was drawn, for the mouse
+    let mut list_area = Rect::default();
+    let mut list_offset = 0;
+    // where each Ratatoskr resource was drawn in the status rows
+    let mut status_segments: Vec<(Rect, String)> = vec![];
+    // time and row of the last click, to recognize double clicks
+    let mut last_click: Option<(Instant, usize)> = None;
 
-    let mut last_icon_path: Option<std::path::PathBuf> = None;
+    let icon_protocol = icon_protocol.unwrap_or_else(Protocol::detect);
+    let show_icons = icon_protocol != Protocol::None;
+    let mut icons = Icons::new(icon_protocol);
+    let mut show_details = config.layout.details;
+    let mut grid_view = ViewState::load().grid;
+    // first visible row of the grid, its size and where each tile was drawn
+    let mut grid_top = 0;
+    let mut grid_cols = 1;
+    let mut grid_rows = 1;
+    let mut grid_tiles: Vec<(Rect, usize)> = vec![];
     // let mut sysinfo = Paragraph::default();
 
     /* let (sender, receiver) = channel::<Paragraph>();
@@ -182,7 +619,28 @@ pub fn run_ui(show_icons: bool, t0: Instant) -> io::Result<()> {
     }); */
 
     let mut apps_entries: Vec<AppEntry> = vec![];
+    let mut processes = ProcessList::new();
+    let mut recent_files: Option<Vec<RecentFile>> = None;
+    let mut projects: Option<Projects> = None;
+    let mut sessions: Option<Sessions> = None;
+    let websearch = WebSearch::new(&config.search);
+    let mut config_errors = config.errors.clone();
+    let theme = Theme::new(&config.theme, &mut config_errors);
+    let keymap = Keymap::new(&config.keys, &mut config_errors);
+    // vim preset: keys are commands until insert mode
+    let mut normal_mode = false;
+    for error in &config_errors {
+        log_to_file(format!("Config: {}", error));
+    }
+    // --open: target and its MIME type, only the apps that can open it are listed
+    let open = open_target.map(|target| (target.to_string(), mime::detect(target)));
+    let mime_apps = if open.is_some() { MimeApps::load() } else { MimeApps::default() };
+    // the apps that can open the target, found again when the app list changes
+    let mut open_handlers: Option<Vec<usize>> = None;
     let mut sock = RatatoskrSocket::new("/tmp/ratatoskr.sock");
+    // with a daemon running, apps and Ratatoskr statuses come from it
+    let mut daemon = DaemonClient::connect();
+    let mut history = if daemon.is_some() { LaunchHistory::default() } else { LaunchHistory::load() };
     let mut spans: HashMap<String, Span> = HashMap::new();
 
     // let mut draws: i64 = 0;
@@ -195,161 +653,838 @@ pub fn run_ui(show_icons: bool, t0: Instant) -> io::Result<()> {
             sysinfo = data.clone();
         } */
 
-        sock.poll_messages();
+        let mut daemon_lost = false;
+        if let Some(client) = &daemon {
+            while let Ok(event) = client.rx.try_recv() {
+                match event {
+                    DaemonEvent::Index { apps, history: h } => {
+                        apps_entries = apps;
+                        open_handlers = None;
+                        history = h;
+                        if t2.is_none() {
+                            t2 = Some(Instant::now());
+                        }
+                    },
+                    DaemonEvent::Status(data) => update_span(&mut spans, data, &theme),
+                    DaemonEvent::Show { mode: m, query } => {
+                        if let Some(m) = m.as_deref().and_then(Mode::from_name) {
+                            mode = m;
+                        }
+                        filter.set(&query.unwrap_or_default());
+                        queries.stop();
+                        selected = 0;
+                    },
+                    DaemonEvent::Disconnected => daemon_lost = true,
+                    // only the answer to an icon request, on its own connection
+                    DaemonEvent::Icon { .. } => {},
+                }
+            }
+        } else {
+            sock.poll_messages();
+
+            if let Ok(data) = sock.rx.try_recv() {
+                // log_to_file(format!("Received: {} {:?}", data.resource, data));
+                // recv.push(data.resource.chars().nth(0).unwrap());
+                update_span(&mut spans, data, &theme);
+            }
+        }
+        if daemon_lost {
+            log_to_file("Daemon disconnected, going standalone".to_string());
+            daemon = None;
+            history = LaunchHistory::load();
+        }
 
-        if let Ok(data) = sock.rx.try_recv() {
-            // log_to_file(format!("Received: {} {:?}", data.resource, data));
-            // recv.push(data.resource.chars().nth(0).unwrap());
-            update_span(&mut spans, data);
+        if mode == Mode::Processes {
+            processes.refresh_if_needed();
+        }
+        if mode == Mode::Recent && recent_files.is_none() {
+            recent_files = Some(load_recent_files());
+        }
+        if mode == Mode::Sessions && sessions.is_none() {
+            sessions = Some(Sessions::load());
+        }
+        if mode == Mode::Projects {
+            projects.get_or_insert_with(|| Projects::load(&config.projects)).poll_status();
         }
 
-        let filtered: Vec<_> = apps_entries.iter()
+        let candidates: Vec<&AppEntry> = match &open {
+            Some((_, mime)) => open_handlers.get_or_insert_with(|| mime_apps.handlers(mime, &apps_entries))
+                .iter()
+                .map(|&i| &apps_entries[i])
+                .collect(),
+            None => apps_entries.iter().collect(),
+        };
+        let mut filtered: Vec<AppRow> = candidates.into_iter()
             .filter(|a| a.name.to_lowercase().contains(&filter.to_lowercase()))
+            .map(AppRow::App)
             .collect();
+        if open.is_none() {
+            let profiles = config.profiles.iter()
+                .filter(|(name, _)| name.to_lowercase().contains(&filter.to_lowercase()))
+                .map(|(name, profile)| AppRow::Profile(name, profile));
+            let group_rows = groups.filtered(&filter).into_iter()
+                .map(|(name, app_ids)| AppRow::Group(name, app_ids));
+            filtered.splice(0..0, profiles.chain(group_rows));
+            if let Some(target) = websearch.explicit_target(&filter) {
+                filtered.insert(0, AppRow::Web(target));
+            } else if filtered.is_empty()
+                && let Some(target) = websearch.default_target(&filter) {
+                filtered.push(AppRow::Web(target));
+            }
+        }
+        let filtered_procs = processes.filtered(&filter);
+        let filtered_recent: Vec<&RecentFile> = recent_files.iter().flatten()
+            .filter(|r| r.name.to_lowercase().contains(&filter.to_lowercase()) || r.folder().to_lowercase().contains(&filter.to_lowercase()))
+            .collect();
+        let filtered_projects = projects.as_ref().map(|p| p.filtered(&filter)).unwrap_or_default();
+        let session_rows = sessions.as_ref().map(|s| s.rows(&filter)).unwrap_or_default();
+        let visible_count = match mode {
+            Mode::Apps => filtered.len(),
+            Mode::Processes => filtered_procs.len(),
+            Mode::Recent => filtered_recent.len(),
+            Mode::Projects => filtered_projects.len(),
+            Mode::Sessions => session_rows.len(),
+        };
+        // the results can shrink under the selection: processes exit, the daemon sends a new index
+        selected = selected.min(visible_count.saturating_sub(1));
 
         let tsize = terminal.size().unwrap();
-        terminal.draw(|f| {
+        icons.receive();
+        let frame = terminal.draw(|f| {
             // draws += 1;
+            // blocks in the layout.order order, the status one has a line per row
+            let constraints: Vec<Constraint> = config.layout.order.iter()
+                .map(|block| match block.as_str() {
+                    "status" => Constraint::Length(config.layout.status_rows.len() as u16),
+                    "list" => Constraint::Min(0),
+                    _ => Constraint::Length(1),
+                })
+                .collect();
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .margin(1)
-                .constraints([
-                    Constraint::Length(1),
-                    Constraint::Length(1),
-                    Constraint::Length(1),
-                    Constraint::Length(1),
-                    Constraint::Min(0),
-                ])
+                .constraints(constraints)
                 .split(f.area());
+            let area = |block: &str| config.layout.order.iter().position(|b| b == block).map(|i| chunks[i]);
 
+            status_segments.clear();
+            if let Some(rect) = area("status") {
+                let lines: Vec<Line> = config.layout.status_rows.iter().enumerate()
+                    .map(|(i, row)| {
+                        if i == 0 && spans.is_empty() {
+                            Line::from("No sys information")
+                        } else if i == 0 && spans.contains_key("ratatoskr") {
+                            Line::from("Ratatoskr disconnected")
+                        } else {
+                            let mut x = rect.x;
+                            let row_spans: Vec<Span> = row.iter()
+                                .filter_map(|k| spans.get(k).map(|s| (k, s.clone())))
+                                .map(|(k, s)| {
+                                    let width = s.width() as u16;
+                                    status_segments.push((Rect::new(x, rect.y + i as u16, width, 1), k.clone()));
+                                    x += width;
+                                    s
+                                })
+                                .collect();
+                            Line::from(row_spans)
+                        }
+                    })
+                    .collect();
+                f.render_widget(Paragraph::new(lines), rect);
+            }
 
-            if spans.len() == 0 {
-                f.render_widget(Span::raw("No sys information"), chunks[0]);
-            } else {
-                // f.render_widget(Span::raw(format!("{} redraws    {} loops    {} spans    {} recv", draws, loops, spans.len(), recv)), chunks[0]);
-                // f.render_widget(Span::raw(format!("{} spans", spans.len())), chunks[0]);
-                if spans.contains_key("ratatoskr") {
-                    f.render_widget(Span::raw("Ratatoskr disconnected"), chunks[0]);
+            if let Some(editor) = &args_editor
+                && let Some(rect) = area("command") {
+                f.render_widget(Paragraph::new(Line::from(vec![
+                    Span::styled("Exec: ", theme.label),
+                    Span::styled(&editor.app.exec, theme.exec),
+                ])), rect);
+            }
+            if let Some(rect) = area("filter") {
+                let line = if let Some(editor) = &args_editor {
+                    let count = presets.for_app(&editor.app.id).len();
+                    let prompt = format!("Command for {}: ", editor.app.name);
+                    let x = rect.x + Span::raw(&prompt).width() as u16 + Span::raw(editor.input.before_cursor()).width() as u16;
+                    f.set_cursor_position(Position::new(x.min(rect.right().saturating_sub(1)), rect.y));
+                    Line::from(vec![
+                        Span::raw(format!("{}{}", prompt, &*editor.input)),
+                        Span::styled(format!("  (Enter: launch, Esc: cancel, Up/Down: {} presets)", count), theme.hint),
+                    ])
+                } else if let Some(name) = &group_name {
+                    Line::from(vec![
+                        Span::raw(format!("Group name: {}", name)),
+                        Span::styled(format!("  (Enter: save {} apps, Esc: cancel)", marked.len()), theme.hint),
+                    ])
+                } else if let Some((process, chosen)) = &signal_picker {
+                    let mut spans = vec![Span::raw(format!("Signal for {} ({}): ", process.name, process.pid))];
+                    for (i, signal) in SIGNALS.iter().enumerate() {
+                        let style = if i == *chosen { theme.highlight } else { Style::default() };
+                        spans.push(Span::styled(signal_name(*signal).trim_start_matches("SIG"), style));
+                        spans.push(Span::raw(" "));
+                    }
+                    spans.push(Span::styled(" (Left/Right: choose, Enter: send, Esc: cancel)", theme.hint));
+                    Line::from(spans)
                 } else {
-                    let keys = ["loadavg", "ram", "disk", "temperature", "volume", "weather", "display"];
-                    // let v: Vec<Span> = spans.values().cloned().collect();
-                    let v: Vec<Span> = keys
-                        .iter()
-                        .filter_map(|k| spans.get(*k).cloned())
-                        .collect();
-                    f.render_widget(Paragraph::new(Line::from(v)), chunks[0]);
+                    let mut spans = vec![Span::raw("Filter: "), Span::raw(&*filter)];
+                    if normal_mode {
+                        spans.push(Span::styled("  -- NORMAL --", theme.hint));
+                    } else if queries.browsing() {
+                        spans.push(Span::styled("  (history: Up/Ctrl+R older, Down newer, Esc keep)", theme.hint));
+                    }
+                    // the cursor stays at the end of the other prompts
+                    let x = rect.x + Span::raw("Filter: ").width() as u16 + Span::raw(filter.before_cursor()).width() as u16;
+                    if failure.is_none() {
+                        f.set_cursor_position(Position::new(x.min(rect.right().saturating_sub(1)), rect.y));
+                    }
+                    Line::from(spans)
+                };
+                f.render_widget(Paragraph::new(line), rect);
+            }
+
+            // icons are loaded only for the rows that are or can soon be on screen
+            let page = list_area.height as usize;
+            let near = |i: usize| i.abs_diff(selected) <= page || (list_offset..list_offset + page).contains(&i);
+            let items: Vec<_> = match mode {
+                Mode::Apps => filtered.iter().enumerate()
+                    .map(|(i, row)| (i, match row {
+                        AppRow::App(a) => {
+                            let icon = show_icons.then(|| {
+                                a.icon_path.as_ref()
+                                    .filter(|_| near(i))
+                                    .and_then(|path| icons.lines(path, 2, 1))
+                                    .map(|mut lines| lines.remove(0).spans)
+                                    .unwrap_or_else(|| vec![Span::raw("  ")])
+                            });
+                            let marker = match () {
+                                _ if marked.is_empty() => "",
+                                _ if marked.contains(&a.id) => "\u{25cf} ",
+                                _ => "  ",
+                            };
+                            app_line(a, marker, icon, &theme, &config.columns)
+                        },
+                        AppRow::Web(w) => web_line(w, &filter, &theme),
+                        AppRow::Group(name, app_ids) => group_line(name, app_ids, &apps_entries, &theme),
+                        AppRow::Profile(name, profile) => profile_line(name, profile, &theme),
+                    }))
+                    .map(|(i, line)| ListItem::new(if i == selected { highlight_text(line, theme.highlight) } else { line }))
+                    .collect(),
+                Mode::Processes => filtered_procs.iter()
+                    .map(|p| ListItem::new(process_line(p, processes.mem_total, &theme)))
+                    .collect(),
+                Mode::Recent => filtered_recent.iter()
+                    .map(|r| ListItem::new(recent_line(r, &theme)))
+                    .collect(),
+                Mode::Projects => filtered_projects.iter()
+                    .map(|p| ListItem::new(project_line(p, &theme)))
+                    .collect(),
+                Mode::Sessions => session_rows.iter()
+                    .map(|r| ListItem::new(session_line(r, &theme)))
+                    .collect(),
+            };
+
+            let title = match mode {
+                Mode::Processes => {
+                    let sort = if processes.sort == SortKey::Cpu { "CPU" } else { "MEM" };
+                    format!("{} by {} (Enter: SIGTERM, Alt+Enter: SIGKILL, Alt+K: other signals, Ctrl+S: sort)", mode.title(), sort)
+                },
+                Mode::Recent => format!("{} (Enter: open, Alt+Enter: open folder)", mode.title()),
+                Mode::Projects if projects.as_ref().is_some_and(|p| !p.scanned) => format!("{} (scanning...)", mode.title()),
+                Mode::Projects => format!("{} (Enter: editor, Alt+Enter: terminal, Ctrl+O: remote)", mode.title()),
+                Mode::Sessions => format!("{} (Enter: attach, type a new name to create it)", mode.title()),
+                Mode::Apps if let Some((target, mime)) = &open => format!("Open {} ({}) with", target, mime),
+                Mode::Apps if !marked.is_empty() => {
+                    format!("{} - {} marked (Enter: launch all, Ctrl+G: save as group, Esc: unmark)", mode.title(), marked.len())
+                },
+                _ => mode.title().to_string(),
+            };
+            let title = match &notice {
+                Some(n) => format!("{} - {}", title, n),
+                None => title,
+            };
+            let mut block = Block::default().borders(Borders::ALL).border_style(theme.border).title(title);
+            if let Some(error) = config_errors.first() {
+                let more = if config_errors.len() > 1 { format!(" (+{} more, see /tmp/fenrir.log)", config_errors.len() - 1) } else { String::new() };
+                block = block.title_bottom(Line::styled(format!("Config: {}{}", error, more), theme.error));
+            }
+            let mut rect = area("list").unwrap_or(f.area());
+            let details_row = filtered.get(selected).filter(|_| mode == Mode::Apps);
+            // the details pane collapses on narrow terminals
+            let details_area = (show_details && mode == Mode::Apps && rect.width >= config.layout.details_min_width).then(|| {
+                let parts = Layout::horizontal([Constraint::Min(0), Constraint::Length(config.layout.details_width)]).split(rect);
+                rect = parts[0];
+                parts[1]
+            });
+            grid_tiles.clear();
+            if grid_view && mode == Mode::Apps {
+                let inner = block.inner(rect);
+                f.render_widget(block, rect);
+                grid_cols = (inner.width / TILE_WIDTH).max(1) as usize;
+                grid_rows = (inner.height / TILE_HEIGHT).max(1) as usize;
+                // the grid scrolls by rows, only as much as needed to show the selected tile
+                let selected_row = selected / grid_cols;
+                grid_top = grid_top.min(selected_row).max((selected_row + 1).saturating_sub(grid_rows));
+                let tile_width = inner.width / grid_cols as u16;
+                let first = grid_top * grid_cols;
+                // the rows of tiles above and below are decoded ahead, after the visible ones
+                if show_icons {
+                    let icon_area = tile_icon_area(Rect::new(0, 0, tile_width, TILE_HEIGHT));
+                    let above = first.saturating_sub(grid_cols)..first;
+                    let below = first + grid_rows * grid_cols..first + (grid_rows + 1) * grid_cols;
+                    for i in above.chain(below) {
+                        if let Some(AppRow::App(a)) = filtered.get(i)
+                            && let Some(path) = &a.icon_path {
+                            icons.prefetch(path, icon_area.width, icon_area.height);
+                        }
+                    }
+                }
+                for (i, row) in filtered.iter().enumerate().skip(first).take(grid_rows * grid_cols) {
+                    let n = i - first;
+                    let tile = Rect::new(inner.x + (n % grid_cols) as u16 * tile_width, inner.y + (n / grid_cols) as u16 * TILE_HEIGHT, tile_width, TILE_HEIGHT)
+                        .intersection(inner);
+                    let icon_path = match row {
+                        AppRow::App(a) => a.icon_path.clone().filter(|_| show_icons),
+                        _ => None,
+                    };
+                    let icon_area = tile_icon_area(tile);
+                    let icon_path = icon_path.filter(|path| icons.load(path, icon_area.width, icon_area.height));
+                    grid_tile(f, tile, row, i == selected, icon_path.is_none(), &theme);
+                    if let Some(path) = icon_path {
+                        icons.render(f, &path, icon_area);
+                    }
+                    grid_tiles.push((tile, i));
+                }
+                list_area = rect;
+                list_offset = first;
+            } else {
+                let list = List::new(items)
+                    .block(block)
+                    // the fg of the selected app row is set on its text only, see highlight_text
+                    .highlight_style(if mode == Mode::Apps { Style { fg: None, ..theme.highlight } } else { theme.highlight })
+                    .scroll_padding(config.layout.scrolloff);
+
+                // the offset is kept between frames, so the list only scrolls when the selection gets near an edge
+                let mut state = ratatui::widgets::ListState::default()
+                    .with_offset(list_offset)
+                    .with_selected(Some(selected));
+                f.render_stateful_widget(list, rect, &mut state);
+                list_area = rect;
+                list_offset = state.offset();
+                // sixel and iTerm2 icons go over the blank cells left in the rows
+                if show_icons && mode == Mode::Apps {
+                    let x = rect.x + if marked.is_empty() { 1 } else { 3 };
+                    let page = rect.height.saturating_sub(2) as usize;
+                    for (n, row) in filtered.iter().skip(list_offset).take(page).enumerate() {
+                        if let AppRow::App(a) = row
+                            && let Some(path) = &a.icon_path
+                            && !icons.inline(path, 2, 1) {
+                            icons.render(f, path, Rect::new(x, rect.y + 1 + n as u16, 2, 1));
+                        }
+                    }
+                }
+                let page = rect.height.saturating_sub(2) as usize;
+                if visible_count > page {
+                    let mut scrollbar = ScrollbarState::new(visible_count).viewport_content_length(page).position(selected);
+                    f.render_stateful_widget(
+                        Scrollbar::new(ScrollbarOrientation::VerticalRight).begin_symbol(None).end_symbol(None).style(theme.border),
+                        rect.inner(Margin { vertical: 1, horizontal: 0 }),
+                        &mut scrollbar,
+                    );
                 }
             }
-            let mut second_row: Vec<Span> = vec![];
-            if spans.contains_key("network") {
-                // f.render_widget(Paragraph::new(spans.get("network").cloned().unwrap_or_default()), chunks[1]);
-                second_row.push(spans.get("network").cloned().unwrap_or_default());
+
+            let app = match details_row {
+                Some(AppRow::App(a)) => Some(a),
+                _ => None,
+            };
+            let icon_path = app.and_then(|a| a.icon_path.clone()).filter(|_| show_icons);
+            // a larger icon at the top of the details pane, or the small one in the bottom right corner of the list
+            let icon_area = match details_area {
+                Some(d) => {
+                    let width = d.width.saturating_sub(4).min(20);
+                    Some(Rect::new(d.x + (d.width - width) / 2, d.y + 1, width, 10.min(d.height.saturating_sub(2))))
+                },
+                None if grid_view && mode == Mode::Apps => None,
+                None => Some(Rect::new(tsize.width.saturating_sub(16), tsize.height.saturating_sub(8), 14, 6)),
+            };
+            // on a tiny terminal the corner icon is cut, or left out
+            let icon_area = icon_area.map(|a| a.intersection(f.area())).filter(|a| !a.is_empty());
+            // the large icons of the rows around the selected one are decoded ahead, the selected one first
+            if let Some(area) = icon_area
+                && show_icons
+                && mode == Mode::Apps {
+                for (i, row) in filtered.iter().enumerate().skip(selected.saturating_sub(2)).take(5) {
+                    if i != selected
+                        && let AppRow::App(a) = row
+                        && let Some(path) = &a.icon_path {
+                        icons.prefetch(path, area.width, area.height);
+                    }
+                }
             }
-            if spans.contains_key("battery") {
-                second_row.push(spans.get("battery").cloned().unwrap_or_default());
+            if let Some(d) = details_area {
+                let icon_rows = match (&icon_path, icon_area) {
+                    (Some(_), Some(area)) => area.height + 1,
+                    _ => 0,
+                };
+                let lines = details_row.map(|row| details_lines(row, &history, &theme, icon_rows)).unwrap_or_default();
+                f.render_widget(
+                    Paragraph::new(lines)
+                        .wrap(Wrap { trim: false })
+                        .block(Block::default().borders(Borders::ALL).border_style(theme.border).title("Details")),
+                    d,
+                );
             }
-            if second_row.len() > 0 {
-                f.render_widget(Paragraph::new(Line::from(second_row)), chunks[1]);
-            } else {
-                f.render_widget(Paragraph::new(""), chunks[1]);
+            if let (Some(path), Some(area)) = (icon_path, icon_area) {
+                icons.render(f, &path, area);
             }
 
-            let input = Paragraph::new(format!("Filter: {}", filter));
-            f.render_widget(input, chunks[3]);
+            if let Some(failure) = &failure {
+                failure_panel(f, failure, edited_command.as_deref(), &theme);
+            }
 
-            let items: Vec<_> = filtered.iter()
-                .map(|a| ListItem::new(
-                    // format!("{} {} - {} - {}", if a.terminal { "" } else { "" }, a.name, a.exec, a.comment)
-                    Line::from(vec![
-                        Span::styled(if a.terminal { "" } else { "" }, Style::default().fg(Color::Gray)),
-                        Span::styled(format!(" {}", a.name), Style::default()),
-                        Span::styled(format!(" {}", a.exec), Style::default().fg(Color::Yellow)),
-                        Span::styled(format!(" {}", a.comment), Style::default().fg(Color::Rgb(128,128,128))),
-                    ])
-                ))
-                .collect();
+            if t1.is_none() {
+                t1 = Some(Instant::now());
+            }
+        })?;
+        icons.flush(frame.buffer);
 
-            let list = List::new(items)
-                .block(Block::default().borders(Borders::ALL).title("Applications"))
-                .highlight_style(Style::default().bg(Color::Blue));
-
-            let mut state = ratatui::widgets::ListState::default();
-            state.select(Some(selected));
-            f.render_stateful_widget(list, chunks[4], &mut state);
-
-            // Icon rendering (Kitty required)
-            let mut config = viuer::Config::default();
-            config.x = tsize.width.saturating_sub(15) as u16 - 1;
-            config.y = tsize.height.saturating_sub(7) as i16 - 1;
-            config.width = Some(14);
-            config.height = Some(6);
-
-            if show_icons {
-                if let Some(app) = filtered.get(selected) {
-                    if last_icon_path != app.icon_path {
-                        let black = image::DynamicImage::new_rgb8(96, 96); // 6x6 terminal cells ≈ 96x96 px
-                                let _ = viuer::print(&black, &config);
-                        if let Some(icon_path) = &app.icon_path {
-                            if let Ok(img) = image::open(icon_path) {
-                                let _ = viuer::print(&img, &config); // viuer::Config::default()
-                                last_icon_path = app.icon_path.clone();
+        // decoded icons and the launched app are checked again soon
+        let timeout = if icons.waiting() || launching.is_some() || log_viewer.is_some() { 15 } else { 100 };
+        let mut event = if event::poll(std::time::Duration::from_millis(timeout))? { Some(event::read()?) } else { None };
+        // nothing else is launched until the running launch or batch is done, its counters would go wrong
+        let busy = launching.is_some() || !pending_launch.is_empty();
+        let running = std::mem::take(&mut pending_launch);
+        let running_label = batch_label.clone();
+        if let Some(Event::Mouse(mouse)) = event
+            && failure.is_none() && args_editor.is_none() && group_name.is_none() && signal_picker.is_none() {
+            event = None;
+            let tile = grid_tiles.iter().find(|(rect, _)| rect.contains(Position::new(mouse.column, mouse.row)));
+            let (in_list, row) = match tile {
+                Some((_, i)) => (true, *i),
+                None if !grid_tiles.is_empty() => (false, 0),
+                None => {
+                    let in_list = mouse.row > list_area.y && mouse.row + 1 < list_area.bottom()
+                        && mouse.column > list_area.x && mouse.column + 1 < list_area.right();
+                    // row of the list under the pointer
+                    (in_list, list_offset + mouse.row.saturating_sub(list_area.y + 1) as usize)
+                },
+            };
+            match mouse.kind {
+                MouseEventKind::ScrollDown => selected = (selected + 3).min(visible_count.saturating_sub(1)),
+                MouseEventKind::ScrollUp => selected = selected.saturating_sub(3),
+                // Ctrl+Click marks the app under the pointer
+                MouseEventKind::Down(MouseButton::Left) if in_list && row < visible_count
+                    && mode == Mode::Apps && open.is_none() && mouse.modifiers.contains(KeyModifiers::CONTROL) => {
+                    if let Some(AppRow::App(app)) = filtered.get(row) {
+                        toggle_mark(&mut marked, &app.id);
+                        selected = row;
+                    }
+                },
+                MouseEventKind::Down(MouseButton::Left) if in_list && row < visible_count => {
+                    let double = last_click.is_some_and(|(time, r)| r == row && time.elapsed().as_millis() < config.mouse.double_click_ms as u128);
+                    selected = row;
+                    if double {
+                        // same as Enter on the row
+                        event = Some(Event::Key(KeyEvent::from(KeyCode::Enter)));
+                        last_click = None;
+                    } else {
+                        last_click = Some((Instant::now(), row));
+                    }
+                },
+                MouseEventKind::Down(MouseButton::Left) => {
+                    let clicked = status_segments.iter()
+                        .find(|(rect, _)| rect.contains(Position::new(mouse.column, mouse.row)));
+                    if let Some((_, resource)) = clicked
+                        && let Some(command) = config.mouse.actions.get(resource) {
+                        pending_launch = vec![LaunchRequest::command(command)];
+                    }
+                },
+                _ => {},
+            }
+        }
+        // bracketed paste: the whole text at once, newlines would end up in the query
+        if let Some(Event::Paste(text)) = &event {
+            let text = text.replace(['\r', '\n'], " ");
+            match (&mut edited_command, &mut args_editor, &mut group_name) {
+                (Some(command), _, _) if failure.is_some() => command.push_str(&text),
+                _ if failure.is_some() || signal_picker.is_some() => {},
+                (_, Some(editor), _) => { editor.input.insert_str(&text); editor.preset = None; },
+                (_, _, Some(name)) => name.push_str(&text),
+                _ => {
+                    filter.insert_str(&text);
+                    queries.stop();
+                    selected = 0;
+                },
+            }
+        }
+        if let Some(Event::Key(key)) = event {
+            // Ctrl+C quits from everywhere, whatever the keymap says
+            if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                break;
+            }
+            if let Some(current) = &failure {
+                // the error panel takes all the keys until it's closed. A retry closes it,
+                // and there's only one retry at a time
+                let retrying = launching.is_some() || !pending_launch.is_empty();
+                match (&mut edited_command, key.code) {
+                    (Some(command), KeyCode::Char(c)) => command.push(c),
+                    (Some(command), KeyCode::Backspace) => { command.pop(); },
+                    (Some(command), KeyCode::Enter) if !retrying => {
+                        pending_launch = vec![LaunchRequest { command: command.clone(), ..current.request.clone() }];
+                        pending_launch.append(&mut queued);
+                        edited_command = None;
+                        failure = None;
+                    },
+                    (Some(_), KeyCode::Esc) => edited_command = None,
+                    (None, KeyCode::Char('r')) if !retrying => {
+                        pending_launch = vec![current.request.clone()];
+                        pending_launch.append(&mut queued);
+                        failure = None;
+                    },
+                    (None, KeyCode::Char('e')) => edited_command = Some(current.request.command.clone()),
+                    (None, KeyCode::Char('l')) => {
+                        match Launch::start(config, &LaunchRequest::command(&format!("xdg-open {}", LAUNCH_LOG))) {
+                            Ok(launch) => log_viewer = Some(launch),
+                            Err(f) => failure = Some(*f),
+                        }
+                    },
+                    (None, KeyCode::Esc) => { failure = None; queued.clear(); batch_label = None; },
+                    _ => {}
+                }
+            } else if let Some(editor) = &mut args_editor {
+                let app_presets = presets.for_app(&editor.app.id);
+                match key.code {
+                    KeyCode::Up if !app_presets.is_empty() => {
+                        let i = editor.preset.map(|i| (i + 1).min(app_presets.len() - 1)).unwrap_or(0);
+                        editor.input.set(&app_presets[i]);
+                        editor.preset = Some(i);
+                    },
+                    KeyCode::Down => {
+                        match editor.preset {
+                            Some(i) if i > 0 => {
+                                editor.input.set(&app_presets[i - 1]);
+                                editor.preset = Some(i - 1);
+                            },
+                            Some(_) => { editor.input.set(&editor.initial); editor.preset = None; },
+                            None => {},
+                        }
+                    },
+                    KeyCode::Enter if !editor.input.trim().is_empty() => {
+                        // the unchanged command line isn't worth a preset
+                        if *editor.input != editor.initial {
+                            presets.remember(&editor.app.id, &editor.input);
+                        }
+                        pending_launch = vec![LaunchRequest::for_app_with_command(&editor.app, &editor.input)];
+                        args_editor = None;
+                    },
+                    KeyCode::Esc => args_editor = None,
+                    _ => {
+                        if editor.input.handle_key(&key) == Some(true) {
+                            editor.preset = None;
+                        }
+                    }
+                }
+            } else if let Some(name) = &mut group_name {
+                match key.code {
+                    KeyCode::Char(c) => name.push(c),
+                    KeyCode::Backspace => { name.pop(); },
+                    KeyCode::Enter if !name.trim().is_empty() => {
+                        groups.save(name, &marked);
+                        notice = Some(format!("Group '{}' saved", name.trim()));
+                        marked.clear();
+                        group_name = None;
+                    },
+                    KeyCode::Esc => group_name = None,
+                    _ => {}
+                }
+            } else if let Some((process, chosen)) = &mut signal_picker {
+                match key.code {
+                    KeyCode::Left | KeyCode::Up => *chosen = (*chosen + SIGNALS.len() - 1) % SIGNALS.len(),
+                    KeyCode::Right | KeyCode::Down | KeyCode::Tab => *chosen = (*chosen + 1) % SIGNALS.len(),
+                    KeyCode::Enter => {
+                        notice = Some(signal_notice(process, SIGNALS[*chosen]));
+                        processes.refresh();
+                        signal_picker = None;
+                    },
+                    KeyCode::Esc => signal_picker = None,
+                    _ => {}
+                }
+            } else {
+                let action = match keymap.resolve(&key, normal_mode) {
+                    Some(Action::LaunchNth(n)) if list_offset + n <= visible_count => {
+                        selected = list_offset + n - 1;
+                        Some(Action::Launch)
+                    },
+                    Some(Action::LaunchNth(_)) => None,
+                    // outside the grid they move the cursor of the filter
+                    Some(Action::SelectLeft | Action::SelectRight) if !(grid_view && mode == Mode::Apps) => None,
+                    // a plain Space marks only with an empty filter, otherwise it's typed
+                    Some(Action::Mark) if !normal_mode && key.code == KeyCode::Char(' ') && key.modifiers.is_empty()
+                        && (!filter.is_empty() || mode != Mode::Apps || open.is_some()) => None,
+                    action => action,
+                };
+                let grid = grid_view && mode == Mode::Apps;
+                let page = if grid { grid_rows * grid_cols } else { (list_area.height as usize).saturating_sub(2).max(1) };
+                match action {
+                    Some(Action::Mode(m)) => {
+                        // sessions come and go: reload them every time
+                        if m == Mode::Sessions {
+                            sessions = None;
+                        }
+                        mode = m; selected = 0; notice = None;
+                    },
+                    Some(Action::ToggleMode) => {
+                        mode = mode.next();
+                        if mode == Mode::Sessions {
+                            sessions = None;
+                        }
+                        selected = 0; notice = None;
+                    },
+                    Some(Action::ToggleDetails) => show_details = !show_details,
+                    Some(Action::ToggleView) if mode == Mode::Apps => {
+                        grid_view = !grid_view;
+                        ViewState { grid: grid_view }.save();
+                    },
+                    Some(Action::PickSignal) if mode == Mode::Processes => {
+                        signal_picker = filtered_procs.get(selected).map(|p| ((*p).clone(), 0));
+                    },
+                    Some(Action::ToggleSort) if mode == Mode::Processes => {
+                        processes.toggle_sort();
+                    },
+                    Some(Action::Mark) if mode == Mode::Apps && open.is_none() => {
+                        if let Some(AppRow::App(app)) = filtered.get(selected) {
+                            toggle_mark(&mut marked, &app.id);
+                            if selected + 1 < visible_count {
+                                selected += 1;
                             }
-                        } else {
-                            last_icon_path = None;
+                        }
+                    },
+                    Some(Action::SaveGroup) if mode == Mode::Apps && !marked.is_empty() => {
+                        group_name = Some(String::new());
+                    },
+                    Some(Action::DeleteGroup) if mode == Mode::Apps => {
+                        if let Some(AppRow::Group(name, _)) = filtered.get(selected) {
+                            let name = name.to_string();
+                            groups.delete(&name);
+                            notice = Some(format!("Group '{}' deleted", name));
+                        }
+                    },
+                    Some(Action::OpenRemote) if mode == Mode::Projects => {
+                        if let Some(p) = filtered_projects.get(selected) {
+                            match p.remote_command() {
+                                Some(command) => pending_launch = vec![LaunchRequest::command(&command)],
+                                None => notice = Some(format!("{} has no remote", p.name)),
+                            }
+                        }
+                    },
+                    Some(Action::EditArgs) if mode == Mode::Apps => {
+                        if let Some(AppRow::App(app)) = filtered.get(selected) {
+                            let args = open.as_ref().map(|(target, _)| app.target_arg(target)).unwrap_or_default();
+                            let initial = app.expand_exec(&args);
+                            args_editor = Some(ArgsEditor { app: (*app).clone(), input: LineEditor::new(&initial), initial, preset: None });
+                        }
+                    },
+                    // Up on an empty query at the top of the list and Ctrl+R browse the past queries.
+                    // With wraparound Up goes to the last row instead, only Ctrl+R starts browsing
+                    Some(Action::SelectPrev) if (filter.is_empty() && selected == 0 && !config.layout.wraparound) || queries.browsing() => {
+                        if let Some(query) = queries.older(&filter) {
+                            filter.set(query);
+                            selected = 0;
+                        }
+                    },
+                    Some(Action::HistorySearch) => {
+                        match queries.older(&filter) {
+                            Some(query) => { filter.set(query); selected = 0; },
+                            None => notice = Some(format!("No past query matching '{}'", &*filter)),
+                        }
+                    },
+                    Some(Action::SelectNext) if queries.browsing() => {
+                        if let Some(query) = queries.newer() {
+                            filter.set(query);
+                            selected = 0;
+                        }
+                    },
+                    Some(Action::SelectPrev) if grid => { selected = selected.checked_sub(grid_cols).unwrap_or(selected); },
+                    // down from the row before a partial last one goes to its last tile
+                    Some(Action::SelectNext) if grid && selected / grid_cols < visible_count.saturating_sub(1) / grid_cols => {
+                        selected = (selected + grid_cols).min(visible_count - 1);
+                    },
+                    Some(Action::SelectNext) if grid => {},
+                    Some(Action::SelectLeft) => { selected = selected.saturating_sub(1); },
+                    Some(Action::SelectRight) if selected + 1 < visible_count => { selected += 1; },
+                    Some(Action::SelectPrev) if selected == 0 && config.layout.wraparound => { selected = visible_count.saturating_sub(1); },
+                    Some(Action::SelectPrev) => { selected = selected.saturating_sub(1); },
+                    Some(Action::SelectNext) if selected + 1 < visible_count => { selected += 1; },
+                    Some(Action::SelectNext) if config.layout.wraparound => { selected = 0; },
+                    Some(Action::PageUp) => { selected = selected.saturating_sub(page); },
+                    Some(Action::PageDown) => { selected = (selected + page).min(visible_count.saturating_sub(1)); },
+                    Some(Action::SelectFirst) => { selected = 0; },
+                    Some(Action::SelectLast) => { selected = visible_count.saturating_sub(1); },
+                    Some(Action::NormalMode) if keymap.has_normal_mode() => normal_mode = true,
+                    Some(Action::InsertMode) => normal_mode = false,
+                    Some(action @ (Action::Launch | Action::LaunchAlt)) => {
+                        queries.record(&filter);
+                        let alt = action == Action::LaunchAlt;
+                        match mode {
+                            Mode::Apps if !marked.is_empty() => {
+                                pending_launch = marked.iter()
+                                    .filter_map(|id| apps_entries.iter().find(|a| &a.id == id))
+                                    .map(LaunchRequest::for_app)
+                                    .collect();
+                            },
+                            Mode::Apps => {
+                                if let Some(AppRow::Group(name, app_ids)) = filtered.get(selected) {
+                                    pending_launch = app_ids.iter()
+                                        .filter_map(|id| apps_entries.iter().find(|a| &a.id == id))
+                                        .map(LaunchRequest::for_app)
+                                        .collect();
+                                    if pending_launch.len() < app_ids.len() {
+                                        log_to_file(format!("Group '{}': {} apps are not installed anymore", name, app_ids.len() - pending_launch.len()));
+                                    }
+                                } else if let Some(AppRow::Profile(name, profile)) = filtered.get(selected) {
+                                    pending_launch = profile_requests(name, profile, &apps_entries);
+                                    batch_label = Some(name.to_string());
+                                } else if let Some(row) = filtered.get(selected) {
+                                    /* let _ = Command::new("sh")
+                                        .arg("-c")
+                                        .arg(&app.exec)
+                                        .spawn(); */
+                                    pending_launch = match row {
+                                        AppRow::App(app) => match &open {
+                                            Some((target, _)) => vec![LaunchRequest::for_app_with_args(app, &app.target_arg(target))],
+                                            None => vec![LaunchRequest::for_app(app)],
+                                        },
+                                        AppRow::Web(w) => vec![LaunchRequest::command(&format!("xdg-open {}", shell_quote(&w.url)))],
+                                        AppRow::Group(..) | AppRow::Profile(..) => vec![],
+                                    };
+                                }
+                            },
+                            Mode::Processes => {
+                                if let Some(p) = filtered_procs.get(selected) {
+                                    let signal = if alt { libc::SIGKILL } else { libc::SIGTERM };
+                                    notice = Some(signal_notice(p, signal));
+                                    processes.refresh();
+                                }
+                            },
+                            Mode::Recent => {
+                                if let Some(r) = filtered_recent.get(selected) {
+                                    let command = if alt { r.open_folder_command() } else { Some(r.open_command()) };
+                                    pending_launch = command.map(|c| LaunchRequest::command(&c)).into_iter().collect();
+                                }
+                            },
+                            Mode::Sessions => {
+                                if let Some(row) = session_rows.get(selected) {
+                                    let command = match row {
+                                        SessionRow::Existing(s) => s.attach_command(config),
+                                        SessionRow::Create(m, name) => create_command(*m, name, config),
+                                    };
+                                    pending_launch = vec![LaunchRequest::command(&command)];
+                                }
+                            },
+                            Mode::Projects => {
+                                if let Some(p) = filtered_projects.get(selected) {
+                                    let command = if alt { p.terminal_command(config) } else { p.editor_command(config) };
+                                    pending_launch = vec![LaunchRequest::command(&command)];
+                                }
+                            },
+                        }
+                    },
+                    Some(Action::Cancel) if queries.browsing() => queries.stop(),
+                    Some(Action::Cancel) if !marked.is_empty() => marked.clear(),
+                    Some(Action::Cancel | Action::Quit) => break,
+                    Some(_) => {},
+                    None if normal_mode => {},
+                    None => {
+                        if filter.handle_key(&key) == Some(true) {
+                            queries.stop();
+                            selected = 0;
                         }
                     }
                 }
             }
+        }
 
-            if t1 == None {
-                t1 = Some(Instant::now());
+        if let Some(result) = log_viewer.as_mut().and_then(Launch::poll) {
+            log_viewer = None;
+            if let Err(f) = result {
+                failure = Some(*f);
             }
-        })?;
+        }
 
-        if event::poll(std::time::Duration::from_millis(100))? {
-            match event::read()? {
-                Event::Key(key) => match key.code {
-                    KeyCode::Char(c) => { filter.push(c); selected = 0; },
-                    KeyCode::Backspace => { filter.pop(); },
-                    KeyCode::Up => { if selected > 0 { selected -= 1; } },
-                    KeyCode::Down => { if selected + 1 < filtered.len() { selected += 1; } },
-                    KeyCode::Enter => {
-                        if let Some(app) = filtered.get(selected) {
-                            /* let _ = Command::new("sh")
-                                .arg("-c")
-                                .arg(&app.exec)
-                                .spawn(); */
-                            launch_detached(app);
-                            std::thread::sleep(std::time::Duration::from_millis(600));
-
-                            break;
+        if busy {
+            if !pending_launch.is_empty() {
+                notice = Some("Still launching, try again when it's done".to_string());
+            }
+            pending_launch = running;
+            batch_label = running_label;
+        }
+
+        // batches launch one app per loop iteration, so their progress gets drawn in between
+        let mut finished = None;
+        if let Some(launch) = &mut launching {
+            if let Some(result) = launch.poll() {
+                finished = Some((launch.request().clone(), result));
+                launching = None;
+            }
+        } else if pending_launch.len() > 1 && batch_total == 0 {
+            batch_total = pending_launch.len();
+            launch_at = Instant::now();
+            notice = Some(batch_progress(batch_label.as_deref(), &pending_launch[0], 1, batch_total));
+        } else if !pending_launch.is_empty() && Instant::now() >= launch_at {
+            let request = pending_launch.remove(0);
+            match Launch::start(config, &request) {
+                Ok(launch) => launching = Some(launch),
+                Err(f) => finished = Some((request, Err(f))),
+            }
+        }
+        if let Some((request, result)) = finished {
+            match result {
+                Ok(_) => {
+                    if let Some(app_id) = &request.app_id {
+                        marked.retain(|id| id != app_id);
+                        match &mut daemon {
+                            Some(client) => client.launched(app_id),
+                            None => history.record(app_id),
                         }
-                    },
-                    KeyCode::Esc => break,
-                    _ => {}
+                    }
+                    let Some(next) = pending_launch.first() else {
+                        break;
+                    };
+                    launch_at = Instant::now() + std::time::Duration::from_millis(config.launch.batch_delay_ms);
+                    notice = Some(batch_progress(batch_label.as_deref(), next, (batch_total + 1).saturating_sub(pending_launch.len()), batch_total));
                 },
-                _ => {}
+                Err(f) => {
+                    edited_command = None;
+                    failure = Some(*f);
+                    queued = std::mem::take(&mut pending_launch);
+                    if batch_total > 1 {
+                        notice = Some(format!("Launched {} of {}", batch_total.saturating_sub(queued.len() + 1), batch_total));
+                    }
+                    batch_total = 0;
+                }
             }
         }
 
-        if apps_entries.len() == 0 {
+        if apps_entries.is_empty() && daemon.is_none() {
             apps_entries = load_app_entries().unwrap_or_default();
-            if t2 == None {
+            open_handlers = None;
+            if t2.is_none() {
                 t2 = Some(Instant::now());
             }
         }
     }
 
+    icons.delete_all();
     disable_raw_mode()?;
-    crossterm::execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
+    crossterm::execute!(terminal.backend_mut(), LeaveAlternateScreen, DisableBracketedPaste)?;
+    if config.mouse.capture {
+        crossterm::execute!(terminal.backend_mut(), DisableMouseCapture)?;
+    }
     terminal.show_cursor()?;
 
-    println!("󰹉 Window realized at {:?}", t1.unwrap() - t0);
-    println!("󱡠 App list visible at {:?}", t2.unwrap() - t0);
+    if let (Some(t1), Some(t2)) = (t1, t2) {
+        println!("󰹉 Window realized at {:?}", t1 - t0);
+        println!("󱡠 App list visible at {:?}", t2 - t0);
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,5 +1,6 @@
 use std::fs::OpenOptions;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 
 pub fn log_to_file(msg: String) {
     let mut file = OpenOptions::new()
@@ -10,6 +11,56 @@ pub fn log_to_file(msg: String) {
     writeln!(file, "[{}] {}", chrono::Local::now().format("%H:%M:%S%.3f"), msg).unwrap();
 }
 
+/// An XDG base directory: `$var` when set, `~/<fallback>` otherwise
+pub fn xdg_home(var: &str, fallback: &str) -> Option<PathBuf> {
+    std::env::var(var).ok()
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var("HOME").ok().map(|h| PathBuf::from(h).join(fallback)))
+}
+
+/// $XDG_DATA_HOME, ~/.local/share by default
+pub fn data_home() -> Option<PathBuf> {
+    xdg_home("XDG_DATA_HOME", ".local/share")
+}
+
+/// Writes `value` as JSON next to `path` and renames it over, so a crash never leaves
+/// a truncated file behind. Errors are logged, `what` names the file in the log
+pub fn save_json<T: serde::Serialize + ?Sized>(path: &Path, value: &T, what: &str) {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let result = path.parent()
+        .map(std::fs::create_dir_all)
+        .unwrap_or(Ok(()))
+        .and_then(|_| serde_json::to_string(value).map_err(std::io::Error::other))
+        .and_then(|json| std::fs::write(&tmp, json))
+        .and_then(|_| std::fs::rename(&tmp, path));
+    if let Err(e) = result {
+        log_to_file(format!("Cannot save {} to {}: {}", what, path.display(), e));
+        let _ = std::fs::remove_file(&tmp);
+    }
+}
+
+/// Quotes a string for `sh -c`
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// "just now", "5m ago", "2h ago", "3d ago"...
+pub fn relative_time(time: chrono::DateTime<chrono::Local>) -> String {
+    let secs = (chrono::Local::now() - time).num_seconds().max(0);
+    match secs {
+        s if s < 60 => "just now".to_string(),
+        s if s < 3600 => format!("{}m ago", s / 60),
+        s if s < 86400 => format!("{}h ago", s / 3600),
+        s if s < 86400 * 7 => format!("{}d ago", s / 86400),
+        s if s < 86400 * 30 => format!("{}w ago", s / (86400 * 7)),
+        s if s < 86400 * 365 => format!("{}mo ago", s / (86400 * 30)),
+        s => format!("{}y ago", s / (86400 * 365)),
+    }
+}
+
 const DEFAULT_WHITE: bool = false;
 pub fn get_color_gradient(value: f64) -> (u8, u8, u8) {
     get_color_gradient_full(0.0, 1.0, value, false)
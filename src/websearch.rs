@@ -0,0 +1,152 @@
+//! Web search keywords ("g rust mpsc", "w Fenrir") and bare URLs
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::Regex;
+use std::collections::HashMap;
+
+use crate::config::SearchConfig;
+
+/// Everything but the RFC 3986 unreserved characters gets encoded
+const QUERY: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// Bare domains without a path only open with one of these, so "main.rs" or "org.gnome" stay queries
+const TLDS: &[&str] = &[
+    "com", "org", "net", "dev", "edu", "gov", "info", "xyz", "de", "fr", "uk", "eu", "nl", "ch", "jp",
+];
+
+/// Also words or file extensions ("import.it", "config.me", "script.pl"): these need
+/// "www.", a port or a path too
+const AMBIGUOUS_TLDS: &[&str] = &[
+    "io", "app", "ai", "co", "me", "it", "es", "at", "be", "se", "no", "pl", "us", "ca",
+];
+
+#[derive(Debug, Clone)]
+pub struct WebTarget {
+    pub label: String,
+    pub url: String,
+}
+
+pub struct WebSearch {
+    engines: HashMap<String, String>,
+    default_engine: String,
+    url_re: Regex,
+}
+
+impl WebSearch {
+    pub fn new(config: &SearchConfig) -> Self {
+        Self {
+            engines: config.all_engines(),
+            default_engine: config.default_engine.clone(),
+            url_re: Regex::new(r"^(https?://\S+|[\w-]+(\.[\w-]+)*\.(?<tld>[a-zA-Z]{2,})(?<port>:\d+)?(?<path>/\S*)?)$").unwrap(),
+        }
+    }
+
+    /// Bare URLs and "keyword query" searches
+    pub fn explicit_target(&self, query: &str) -> Option<WebTarget> {
+        let query = query.trim();
+        if self.is_url(query) {
+            let url = if query.contains("://") { query.to_string() } else { format!("https://{}", query) };
+            return Some(WebTarget { label: "Open URL".into(), url });
+        }
+        let (keyword, terms) = query.split_once(' ')?;
+        let template = self.engines.get(keyword)?;
+        let terms = terms.trim();
+        if terms.is_empty() {
+            return None;
+        }
+        Some(WebTarget {
+            label: format!("Search {}", keyword),
+            url: expand_template(template, terms),
+        })
+    }
+
+    fn is_url(&self, query: &str) -> bool {
+        let Some(captures) = self.url_re.captures(query) else {
+            return false;
+        };
+        let Some(tld) = captures.name("tld") else {
+            return true;
+        };
+        let tld = tld.as_str().to_lowercase();
+        let qualified = query.to_lowercase().starts_with("www.") || captures.name("port").is_some();
+        captures.name("path").is_some()
+            || TLDS.contains(&tld.as_str())
+            || (qualified && AMBIGUOUS_TLDS.contains(&tld.as_str()))
+    }
+
+    /// Search with the default engine, used when nothing else matches
+    pub fn default_target(&self, query: &str) -> Option<WebTarget> {
+        let query = query.trim();
+        if query.is_empty() {
+            return None;
+        }
+        let template = self.engines.get(&self.default_engine)?;
+        Some(WebTarget {
+            label: format!("Search {}", self.default_engine),
+            url: expand_template(template, query),
+        })
+    }
+}
+
+pub fn expand_template(template: &str, query: &str) -> String {
+    template.replace("{query}", &utf8_percent_encode(query, QUERY).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search() -> WebSearch {
+        WebSearch::new(&SearchConfig::default())
+    }
+
+    fn url(query: &str) -> Option<String> {
+        search().explicit_target(query).filter(|t| t.label == "Open URL").map(|t| t.url)
+    }
+
+    #[test]
+    fn encoding() {
+        let template = "https://example.com/?q={query}";
+        assert_eq!(expand_template(template, "rust mpsc"), "https://example.com/?q=rust%20mpsc");
+        assert_eq!(expand_template(template, "a&b=c"), "https://example.com/?q=a%26b%3Dc");
+        assert_eq!(expand_template(template, "c# tips"), "https://example.com/?q=c%23%20tips");
+        assert_eq!(expand_template(template, "c++"), "https://example.com/?q=c%2B%2B");
+        assert_eq!(expand_template(template, "caffè 日本"), "https://example.com/?q=caff%C3%A8%20%E6%97%A5%E6%9C%AC");
+        assert_eq!(expand_template(template, "a-b_c.d~"), "https://example.com/?q=a-b_c.d~");
+    }
+
+    #[test]
+    fn urls() {
+        assert_eq!(url("https://example.org/x?y=1").as_deref(), Some("https://example.org/x?y=1"));
+        assert_eq!(url("github.com/vncnz").as_deref(), Some("https://github.com/vncnz"));
+        assert_eq!(url("docs.rs/serde").as_deref(), Some("https://docs.rs/serde"));
+        assert_eq!(url("wiki.archlinux.org").as_deref(), Some("https://wiki.archlinux.org"));
+        assert_eq!(url("localhost.dev:8080").as_deref(), Some("https://localhost.dev:8080"));
+    }
+
+    #[test]
+    fn dotted_queries_are_not_urls() {
+        assert_eq!(url("main.rs"), None);
+        assert_eq!(url("org.gnome"), None);
+        assert_eq!(url("org.gnome.Nautilus"), None);
+        assert_eq!(url("config.toml"), None);
+        assert_eq!(url("firefox"), None);
+        // TLDs that are also words or extensions
+        assert_eq!(url("config.me"), None);
+        assert_eq!(url("import.it"), None);
+        assert_eq!(url("std.io"), None);
+        assert_eq!(url("script.pl"), None);
+        assert_eq!(url("www.corriere.it").as_deref(), Some("https://www.corriere.it"));
+        assert_eq!(url("example.io/docs").as_deref(), Some("https://example.io/docs"));
+        assert_eq!(url("vite.app:5173").as_deref(), Some("https://vite.app:5173"));
+    }
+
+    #[test]
+    fn keywords() {
+        let target = search().explicit_target("g rust mpsc").unwrap();
+        assert_eq!(target.label, "Search g");
+        assert!(target.url.ends_with("rust%20mpsc"));
+        assert!(search().explicit_target("g ").is_none());
+        assert!(search().explicit_target("nokeyword here").is_none());
+    }
+}